@@ -1,4 +1,4 @@
-use syn::{spanned::Spanned, DeriveInput, Expr, Lit, ExprLit, Data, DataStruct, Type, Path, Ident, PathArguments, AngleBracketedGenericArguments, GenericArgument};
+use syn::{spanned::Spanned, DeriveInput, Expr, Lit, ExprLit, Data, DataStruct, DataEnum, Fields, Type, Path, Ident, PathArguments, AngleBracketedGenericArguments, GenericArgument, Attribute, Meta, MetaNameValue, LitStr, Token};
 use quote::{quote, quote_spanned, ToTokens};
 use proc_macro2::{TokenStream, Span};
 use convert_case::{Case, Casing};
@@ -11,18 +11,16 @@ pub fn derive_generatable(item: proc_macro::TokenStream) -> proc_macro::TokenStr
 
     let mut slot: Option<String> = None;
     for attr in input.attrs {
-        if let Ok(expr) = attr.parse_args::<Expr>() {
-            if let Expr::Assign(assign) = expr {
-                let value = if let Expr::Lit(ExprLit { lit: Lit::Str(right), .. }) = *assign.right {
-                    right.value()
-                } else {
-                    String::new()
-                };
-                let left_str = assign.left.to_token_stream().to_string();
-                if &left_str == "slot" {
-                    slot = Some(value);
-                    continue;
-                }
+        if let Ok(Expr::Assign(assign)) = attr.parse_args::<Expr>() {
+            let value = if let Expr::Lit(ExprLit { lit: Lit::Str(right), .. }) = *assign.right {
+                right.value()
+            } else {
+                String::new()
+            };
+            let left_str = assign.left.to_token_stream().to_string();
+            if &left_str == "slot" {
+                slot = Some(value);
+                continue;
             }
         }
     }
@@ -70,6 +68,10 @@ pub fn derive_generatable(item: proc_macro::TokenStream) -> proc_macro::TokenStr
                 #(#attrs)*
                 attr
             }
+
+            fn as_any(&self) -> &dyn std::any::Any {
+                self
+            }
         }
 
         impl Into<Box<dyn crate::generators::Generatable>> for #name {
@@ -80,6 +82,156 @@ pub fn derive_generatable(item: proc_macro::TokenStream) -> proc_macro::TokenStr
     }.into()
 }
 
+/// Implements `crate::schema::JsonSchema` from the serde attributes and doc comments of a struct or unit enum.
+/// Types are added to the definitions under their name without a `Yaml` prefix, fields documented with `///` get a description.
+/// Field aliases are listed as properties of their own
+#[proc_macro_derive(JsonSchema)]
+pub fn derive_json_schema(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = syn::parse_macro_input!(item as DeriveInput);
+    let name = input.ident;
+    let definition = strip_prefix(name.to_string(), "Yaml");
+    let description = doc_comment(&input.attrs);
+    let container = SerdeAttributes::parse(&input.attrs);
+
+    let schema = match input.data {
+        Data::Struct(DataStruct { fields: Fields::Named(fields), .. }) => {
+            let properties = fields.named.iter().filter_map(|f| {
+                let serde = SerdeAttributes::parse(&f.attrs);
+                if serde.skip {
+                    return None;
+                }
+                let key = serde.rename.unwrap_or_else(|| strip_prefix(f.ident.as_ref().unwrap().to_string(), "r#"));
+                let ty = &f.ty;
+                let doc = doc_comment(&f.attrs);
+                let required = !serde.default && !is_option(ty);
+                let alias_description = format!("Alias of `{key}`");
+                let aliases = serde.aliases.iter().map(|alias| quote! {
+                    properties.insert(#alias.to_owned(), crate::schema::describe(schema.clone(), #alias_description));
+                });
+                Some(quote_spanned! { f.span() =>
+                    let schema = <#ty as crate::schema::JsonSchema>::schema(definitions);
+                    #(#aliases)*
+                    properties.insert(#key.to_owned(), crate::schema::describe(schema, #doc));
+                    if #required {
+                        required.push(#key.into());
+                    }
+                })
+            });
+            quote! {
+                let mut properties = serde_json::Map::new();
+                let mut required = Vec::<serde_json::Value>::new();
+                #(#properties)*
+                let mut schema = serde_json::json!({
+                    "type": "object",
+                    "properties": properties,
+                    "additionalProperties": false,
+                });
+                if !required.is_empty() {
+                    schema["required"] = required.into();
+                }
+                schema
+            }
+        },
+        // Newtypes are written like the type they wrap
+        Data::Struct(DataStruct { fields: Fields::Unnamed(fields), .. }) if fields.unnamed.len() == 1 => {
+            let ty = &fields.unnamed.first().unwrap().ty;
+            quote! { <#ty as crate::schema::JsonSchema>::schema(definitions) }
+        },
+        Data::Enum(DataEnum { variants, .. }) => {
+            let case = match container.rename_all.as_deref() {
+                Some("snake_case") => Some(Case::Snake),
+                Some("kebab-case") => Some(Case::Kebab),
+                Some("lowercase") => Some(Case::Flat),
+                Some(other) => panic!("unsupported rename_all '{other}'"),
+                None => None,
+            };
+            let values = variants.iter().map(|v| {
+                if !matches!(v.fields, Fields::Unit) {
+                    panic!("JsonSchema derive on an enum with data in variant '{}'", v.ident)
+                }
+                let serde = SerdeAttributes::parse(&v.attrs);
+                serde.rename.unwrap_or_else(|| match case {
+                    Some(case) => v.ident.to_string().to_case(case),
+                    None => v.ident.to_string(),
+                })
+            });
+            quote! {
+                serde_json::json!({
+                    "type": "string",
+                    "enum": [#(#values),*],
+                })
+            }
+        },
+        _ => panic!("JsonSchema derive on a invalid data structure"),
+    };
+
+    quote! {
+        impl crate::schema::JsonSchema for #name {
+            fn schema(definitions: &mut serde_json::Map<String, serde_json::Value>) -> serde_json::Value {
+                if !definitions.contains_key(#definition) {
+                    // Taken before the fields are added, for types which contain themselves
+                    definitions.insert(#definition.to_owned(), serde_json::Value::Null);
+                    let schema = { #schema };
+                    definitions.insert(#definition.to_owned(), crate::schema::describe(schema, #description));
+                }
+                crate::schema::reference(#definition)
+            }
+        }
+    }.into()
+}
+
+/// The serde attributes which change how a type is written
+#[derive(Default)]
+struct SerdeAttributes {
+    rename: Option<String>,
+    rename_all: Option<String>,
+    aliases: Vec<String>,
+    default: bool,
+    skip: bool,
+}
+
+impl SerdeAttributes {
+    fn parse(attrs: &[Attribute]) -> Self {
+        let mut serde = SerdeAttributes::default();
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("serde")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    serde.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("rename_all") {
+                    serde.rename_all = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("alias") {
+                    serde.aliases.push(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("default") {
+                    serde.default = true;
+                } else if meta.path.is_ident("skip") || meta.path.is_ident("skip_deserializing") {
+                    serde.skip = true;
+                }
+                // Values of other attributes, like `default = "path"`
+                if meta.input.peek(Token![=]) {
+                    meta.value()?.parse::<Expr>()?;
+                }
+                Ok(())
+            }).expect("invalid serde attribute");
+        }
+        serde
+    }
+}
+
+/// The lines of the `///` comments, joined with spaces
+fn doc_comment(attrs: &[Attribute]) -> String {
+    attrs.iter()
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(MetaNameValue { path, value: Expr::Lit(ExprLit { lit: Lit::Str(doc), .. }), .. }) if path.is_ident("doc") => Some(doc.value().trim().to_owned()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn is_option(ty: &Type) -> bool {
+    matches!(ty, Type::Path(path) if path.path.segments.last().is_some_and(|segment| segment.ident == "Option"))
+}
+
 fn gen_attr_line(span: Span, field_name: &Ident, path: &Path, append_self: bool) -> TokenStream {
     let self_dot = if append_self {
         quote!{ self. }
@@ -90,17 +242,17 @@ fn gen_attr_line(span: Span, field_name: &Ident, path: &Path, append_self: bool)
     if path.is_ident("String") {
         quote_spanned! { span =>
             attr.insert(#field_name_kebab.to_owned(), #self_dot #field_name);
-        }.into()
+        }
     } else if path.is_ident("bool") {
         quote_spanned! { span =>
             if #self_dot #field_name {
                 attr.insert(#field_name_kebab.to_owned(), "".to_owned());
             }
-        }.into()
+        }
     } else {
         quote_spanned! { span =>
             attr.insert(#field_name_kebab.to_owned(), #self_dot #field_name.to_string());
-        }.into()
+        }
     }
 }
 
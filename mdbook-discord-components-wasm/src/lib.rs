@@ -1,17 +1,18 @@
-use mdbook_discord_components::{parsers::{DiscordCodeBlock, YamlParser}, generators::html::HTMLGenerator};
+use std::rc::Rc;
+use mdbook_discord_components::{parsers::{new_code_block, YamlParser, PARSER_NAMES}, generators::html::HTMLGenerator, config::Config};
 use pulldown_cmark::Event;
 use wasm_bindgen::{prelude::wasm_bindgen, JsValue};
 
 #[wasm_bindgen]
 pub fn parse(parser_name: String, input: String) -> Result<String, String> {
-    let mut context = match parser_name.as_str() {
-        "yaml" => DiscordCodeBlock::<YamlParser>::new("yaml".to_owned(), "wasm input".to_owned(), false),
-        _ => return Err("".to_owned()),
+    let config = Rc::new(Config { embed_script: false, ..Default::default() });
+    let Some(mut context) = new_code_block::<HTMLGenerator>(&parser_name, parser_name.clone(), "wasm input".to_owned(), config) else {
+        return Err(format!("unknown parser '{parser_name}'"));
     };
 
-    context.push_code(input);
+    context.push_code(&input);
 
-    let events = context.build::<HTMLGenerator>();
+    let events = context.build();
     match events {
         Ok(ev) => {
             if let Some(Event::Html(inner)) = ev.first() {
@@ -27,6 +28,14 @@ pub fn parse(parser_name: String, input: String) -> Result<String, String> {
 #[wasm_bindgen]
 pub fn available_parsers() -> js_sys::Array {
     let array = js_sys::Array::new();
-    array.push(&JsValue::from_str("yaml"));
+    for parser in PARSER_NAMES {
+        array.push(&JsValue::from_str(parser));
+    }
     array
 }
+
+/// JSON Schema of the messages of the `yaml` parser
+#[wasm_bindgen]
+pub fn schema() -> String {
+    YamlParser::schema().to_string()
+}
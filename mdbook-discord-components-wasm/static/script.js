@@ -1,17 +1,25 @@
-import init, { parse } from "./pkg/mdbook_discord_components_wasm.js";
+import init, { parse, available_parsers } from "./pkg/mdbook_discord_components_wasm.js";
 
 const wasm = await init();
 
 const main = document.getElementsByTagName("main")[0];
 const input = document.getElementById("input");
 const button = document.getElementById("run");
+const parser = document.getElementById("parser");
+
+for (const name of available_parsers()) {
+    const option = document.createElement("option");
+    option.value = name;
+    option.textContent = name;
+    parser.appendChild(option);
+}
 
 button.addEventListener("click", () => {
     if (!wasm || !main || !input)
         return;
     let inputText = input.value;
     try {
-        let out = parse("yaml", inputText);
+        let out = parse(parser.value, inputText);
         console.info("Parser produced", `\n${out}`);
         main.innerHTML = out;
     } catch (err) {
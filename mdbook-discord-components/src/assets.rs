@@ -0,0 +1,57 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+use anyhow::{Result, Context};
+
+/// Name of the vendored discord-components script in the book's source directory, which mdBook copies into the output
+pub static SCRIPT_FILE_NAME: &str = "discord-components.js";
+
+/// The pinned discord-components release, bundled into a single script by `scripts/bundle-discord-components.sh`.
+/// Its SHA-256 hash is in `assets/discord-components.js.sha256`
+#[cfg(feature = "bundled-script")]
+static SCRIPT: Option<&str> = Some(include_str!("../assets/discord-components.js"));
+#[cfg(not(feature = "bundled-script"))]
+static SCRIPT: Option<&str> = None;
+
+/// Writes the bundled script into `dir` and returns its path
+///
+/// An up to date copy isn't written again, so `mdbook serve` doesn't rebuild the book because of it.
+pub fn install_script(dir: &Path) -> Result<PathBuf> {
+    let script = SCRIPT.ok_or_else(|| InstallError::new(
+        "This build doesn't bundle the discord-components script, rebuild it with the `bundled-script` feature"
+    ).anyhow())?;
+    let path = dir.join(SCRIPT_FILE_NAME);
+    if fs::read_to_string(&path).map(|installed| installed == script).unwrap_or(false) {
+        return Ok(path);
+    }
+    fs::create_dir_all(dir)
+        .and_then(|_| fs::write(&path, script))
+        .with_context(|| format!("Failed to write the discord-components script to '{}'", path.display()))?;
+    Ok(path)
+}
+
+#[derive(Debug)]
+struct InstallError {
+    message: String,
+}
+
+impl InstallError {
+    fn new<S: Into<String>>(message: S) -> Self {
+        Self { message: message.into() }
+    }
+
+    fn anyhow(self) -> anyhow::Error {
+        anyhow::Error::new(self)
+    }
+}
+
+impl Display for InstallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for InstallError {}
@@ -1,14 +1,20 @@
 use std::collections::HashMap;
+use crate::config::DisplayOptions;
 use crate::generators::Generatable;
 
 pub mod message;
 pub mod embed;
+#[allow(clippy::module_inception)]
 pub mod components;
 pub mod invite;
 
 #[derive(Default)]
 pub struct Components {
     pub roles: HashMap<String, String>,
+    /// Names of the users mentioned by ID
+    pub users: HashMap<u64, String>,
+    /// Display options of the code block
+    pub display: DisplayOptions,
     pub tree: Vec<ComponentTree>,
 }
 
@@ -19,3 +25,35 @@ pub enum ComponentTree {
         nodes: Vec<ComponentTree>,
     },
 }
+
+impl ComponentTree {
+    /// The data of the node if it's a `T`
+    pub fn data<T: 'static>(&self) -> Option<&T> {
+        match self {
+            ComponentTree::Node { data, .. } => data.as_any().downcast_ref(),
+            ComponentTree::Text(_) => None,
+        }
+    }
+
+    pub fn nodes(&self) -> &[ComponentTree] {
+        match self {
+            ComponentTree::Node { nodes, .. } => nodes,
+            ComponentTree::Text(_) => &[],
+        }
+    }
+
+    /// The child nodes of which the data is a `T`
+    pub fn children<T: 'static>(&self) -> impl Iterator<Item = (&T, &ComponentTree)> {
+        self.nodes().iter().filter_map(|node| node.data::<T>().map(|data| (data, node)))
+    }
+
+    /// The text directly inside the node, like the content of a message
+    pub fn text(&self) -> String {
+        match self {
+            ComponentTree::Text(text) => text.clone(),
+            ComponentTree::Node { nodes, .. } => nodes.iter()
+                .filter_map(|node| if let ComponentTree::Text(text) = node { Some(text.as_str()) } else { None })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    fs,
+};
+use serde::{de::Error, Deserialize, Deserializer};
+use chrono::{DateTime, FixedOffset, Locale};
+use chrono_tz::Tz;
+use anyhow::{Result, Context};
+
+use crate::emoji::TWEMOJI_BASE_URL;
+use crate::parsers::DISCORD_COMPONENTS_SCRIPT_URL;
+use crate::validation::Validator;
+
+/// Book-wide configuration, read from the `[preprocessor.discord-components]` table of `book.toml`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Config {
+    /// What the `html` renderer gets, discord-components elements or self-contained HTML and CSS
+    pub output: Output,
+    /// Whether the first discord block of a chapter includes the discord-components script,
+    /// disabled when the theme loads it instead
+    pub embed_script: bool,
+    /// Where the discord-components script is loaded from, an URL or a path relative to the book root
+    pub script_src: String,
+    /// Subresource integrity hash of the script, like `sha384-...`
+    pub script_integrity: Option<String>,
+    /// Installs the script bundled into the preprocessor in the book's source directory and loads it from there
+    pub vendor_script: bool,
+    /// Named authors which messages can reference with `as: <key>`
+    pub personas: HashMap<String, Persona>,
+    /// A YAML file of additional personas, relative to the book root
+    pub personas_file: Option<PathBuf>,
+    /// Roles by ID, for `<@&id>` mentions
+    pub roles: HashMap<String, Role>,
+    /// Channel names by ID, for `<#id>` mentions
+    pub channels: HashMap<String, String>,
+    /// Where the Twemoji images of standard emoji are loaded from
+    pub emoji_base_url: String,
+    /// The RFC 3339 date relative `<t:unix:R>` timestamps are formatted against.
+    /// When unset, `SOURCE_DATE_EPOCH` or the build time, which makes relative timestamps change from build to build
+    #[serde(deserialize_with = "deserialize_now")]
+    pub now: Option<DateTime<FixedOffset>>,
+    /// IANA time zone timestamps are displayed in
+    #[serde(deserialize_with = "deserialize_timezone")]
+    pub timezone: Tz,
+    /// Locale of the timestamp date and time formats, like `en_US` or `de-DE`
+    #[serde(deserialize_with = "deserialize_locale")]
+    pub locale: Locale,
+    /// How HTML written in message content is treated
+    pub content_policy: ContentPolicy,
+    /// Tags kept by the `allowlist` content policy
+    pub allowed_tags: Vec<String>,
+    /// Theme of the messages, `auto` follows the theme picked in mdBook
+    pub theme: Theme,
+    /// Shows messages in compact mode, with the timestamp and author in front of the content
+    pub compact: bool,
+    /// Leaves out the background behind the messages
+    pub no_background: bool,
+    /// What happens to a block which fails to parse
+    pub on_error: OnError,
+    /// Fails blocks with messages over Discord's limits, like content longer than 2000 characters, instead of warning about them
+    pub strict_limits: bool,
+    /// Fails blocks with keys which aren't fields of the YAML model, like misspelled ones, instead of warning about them
+    pub deny_unknown_fields: bool,
+    /// Checks the messages of every block, library users can add their own rules
+    #[serde(skip)]
+    pub validator: Validator,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            output: Output::default(),
+            embed_script: true,
+            script_src: DISCORD_COMPONENTS_SCRIPT_URL.to_owned(),
+            script_integrity: None,
+            vendor_script: false,
+            personas: HashMap::new(),
+            personas_file: None,
+            roles: HashMap::new(),
+            channels: HashMap::new(),
+            emoji_base_url: TWEMOJI_BASE_URL.to_owned(),
+            now: None,
+            timezone: Tz::UTC,
+            locale: Locale::en_US,
+            content_policy: ContentPolicy::default(),
+            allowed_tags: ["b", "i", "u", "s", "em", "strong", "del", "ins", "sub", "sup", "small", "mark", "kbd", "span", "br"]
+                .map(str::to_owned)
+                .to_vec(),
+            theme: Theme::default(),
+            compact: false,
+            no_background: false,
+            on_error: OnError::default(),
+            strict_limits: false,
+            deny_unknown_fields: false,
+            validator: Validator::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The book-wide display options, which code blocks can override
+    pub fn display(&self) -> DisplayOptions {
+        DisplayOptions { theme: self.theme, compact: self.compact, no_background: self.no_background }
+    }
+
+    /// Loads `personas_file`, personas defined in `book.toml` take precedence over the ones from the file
+    pub fn load_personas_file(&mut self, root: &Path) -> Result<()> {
+        if let Some(file) = self.personas_file.as_ref() {
+            let path = root.join(file);
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read personas file '{}'", path.display()))?;
+            let mut personas = serde_yaml::from_str::<HashMap<String, Persona>>(&content)
+                .with_context(|| format!("Failed to parse personas file '{}'", path.display()))?;
+            personas.extend(self.personas.drain());
+            self.personas = personas;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Theme {
+    /// Light with mdBook's `light` and `rust` themes, dark with the others
+    #[default]
+    Auto,
+    Dark,
+    Light,
+}
+
+/// How a block of messages is displayed
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DisplayOptions {
+    pub theme: Theme,
+    pub compact: bool,
+    pub no_background: bool,
+}
+
+impl DisplayOptions {
+    /// Applies an option of a code block's arguments, like `theme=light`, `compact` or `compact=false`.
+    /// Returns whether the word was a display option
+    pub fn apply(&mut self, word: &str) -> bool {
+        let (key, value) = match word.split_once('=') {
+            Some((key, value)) => (key, Some(value)),
+            None => (word, None),
+        };
+        let flag = match value {
+            None | Some("true") => Some(true),
+            Some("false") => Some(false),
+            Some(_) => None,
+        };
+        match (key, value, flag) {
+            ("theme", Some("auto"), _) => self.theme = Theme::Auto,
+            ("theme", Some("dark"), _) => self.theme = Theme::Dark,
+            ("theme", Some("light"), _) => self.theme = Theme::Light,
+            ("compact", _, Some(flag)) => self.compact = flag,
+            ("no-background", _, Some(flag)) => self.no_background = flag,
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Output {
+    /// discord-components elements, displayed by the discord-components script
+    #[default]
+    Components,
+    /// Plain HTML with a bundled stylesheet, which needs neither the script nor a network connection
+    Static,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnError {
+    /// Stops the build
+    #[default]
+    Fail,
+    /// Logs the error and leaves the block as written
+    Warn,
+    /// Replaces the block with a panel showing the error and the block's code
+    Render,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContentPolicy {
+    /// HTML is inserted as written
+    #[default]
+    Raw,
+    /// Only `allowed-tags` with `style` and `title` attributes are kept, other HTML is escaped
+    Allowlist,
+    /// All HTML is escaped and shown as text
+    Escape,
+}
+
+/// A guild role, mentions are colored with its color
+#[derive(Debug, Deserialize)]
+pub struct Role {
+    pub name: String,
+    pub color: Option<String>,
+}
+
+fn deserialize_now<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<DateTime<FixedOffset>>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|now| DateTime::parse_from_rfc3339(&now).map_err(|err| D::Error::custom(format!("invalid date '{now}': {err}"))))
+        .transpose()
+}
+
+fn deserialize_timezone<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Tz, D::Error> {
+    let timezone = String::deserialize(deserializer)?;
+    timezone.parse().map_err(|_| D::Error::custom(format!("unknown time zone '{timezone}'")))
+}
+
+fn deserialize_locale<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Locale, D::Error> {
+    let locale = String::deserialize(deserializer)?;
+    locale.replace('-', "_").parse().map_err(|_| D::Error::custom(format!("unknown locale '{locale}'")))
+}
+
+/// Author data shared by messages, replies and slash commands
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Persona {
+    pub user_id: Option<u64>,
+    pub username: Option<String>,
+    pub avatar: Option<String>,
+    pub color: Option<String>,
+    pub bot: Option<bool>,
+    pub verified: Option<bool>,
+}
+
+impl Persona {
+    /// Overwrites fields with the ones set in `other`
+    pub fn merge(&mut self, other: Persona) {
+        if other.user_id.is_some() {
+            self.user_id = other.user_id;
+        }
+        if other.username.is_some() {
+            self.username = other.username;
+        }
+        if other.avatar.is_some() {
+            self.avatar = other.avatar;
+        }
+        if other.color.is_some() {
+            self.color = other.color;
+        }
+        if other.bot.is_some() {
+            self.bot = other.bot;
+        }
+        if other.verified.is_some() {
+            self.verified = other.verified;
+        }
+    }
+}
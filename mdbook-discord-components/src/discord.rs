@@ -1,3 +1,4 @@
+#[cfg(feature = "http")]
 use std::{
     collections::HashMap,
     fmt::Display,
@@ -7,32 +8,35 @@ use std::{
 };
 use serde_aux::field_attributes::deserialize_number_from_string;
 use serde::Deserialize;
+#[cfg(feature = "http")]
 use anyhow::Result;
+#[cfg(feature = "http")]
 use oxhttp::{
     model::{Request, Method, Status},
     Client,
 };
 
+#[cfg(feature = "http")]
 static DISCORD_API: &str = "https://discord.com/api/v10/";
-static DISCORD_CDN: &str = "https://cdn.discordapp.com/";
+pub static DISCORD_CDN: &str = "https://cdn.discordapp.com/";
+static VERIFIED_BOT_FLAG: u64 = 1 << 16;
 
+#[cfg(feature = "http")]
 pub struct Discord {
     client: Client,
     token: Option<String>,
     cache: RwLock<HashMap<u64, User>>,
 }
 
+#[cfg(feature = "http")]
 impl Default for Discord {
     fn default() -> Self {
-        let token = if let Ok(token) = env::var("DISCORD_TOKEN") {
-            Some(token)
-        } else {
-            None
-        };
+        let token = env::var("DISCORD_TOKEN").ok();
         Self { token, client: Client::new(), cache: RwLock::default() }
     }
 }
 
+#[cfg(feature = "http")]
 impl Discord {
     pub fn user(&self, user_id: u64) -> Option<User> {
         if let Some(token) = self.token.clone() {
@@ -67,14 +71,20 @@ impl Discord {
 pub struct User {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     id: u64,
+    #[serde(default)]
     discriminator: String,
     username: String,
     global_name: Option<String>,
     avatar: Option<String>,
     bot: Option<bool>,
+    public_flags: Option<u64>,
 }
 
 impl User {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
     pub fn display_name(&self) -> String {
         if let Some(global_name) = self.global_name.as_ref() {
             global_name.clone()
@@ -100,13 +110,19 @@ impl User {
         }
         false
     }
+
+    pub fn is_verified_bot(&self) -> bool {
+        self.public_flags.map(|f| f & VERIFIED_BOT_FLAG != 0).unwrap_or(false)
+    }
 }
 
+#[cfg(feature = "http")]
 #[derive(Debug)]
 struct DiscordError {
     message: String,
 }
 
+#[cfg(feature = "http")]
 impl DiscordError {
     fn new<S: Into<String>>(message: S) -> Self {
         Self { message: message.into() }
@@ -117,10 +133,12 @@ impl DiscordError {
     }
 }
 
+#[cfg(feature = "http")]
 impl Display for DiscordError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.message)
     }
 }
 
+#[cfg(feature = "http")]
 impl Error for DiscordError {}
@@ -0,0 +1,188 @@
+use std::iter;
+use emojis::{Emoji, SkinTone};
+use regex::{Regex, Captures};
+
+use crate::discord::DISCORD_CDN;
+
+/// Default location of the Twemoji images, `emoji-base-url` can point it to a self-hosted copy
+pub static TWEMOJI_BASE_URL: &str = "https://cdn.jsdelivr.net/gh/jdecked/twemoji@15.1.0/assets/svg/";
+
+/// Longest emoji sequence in characters, like a family with skin tones
+static MAX_SEQUENCE_LENGTH: usize = 10;
+
+/// Symbols which are only emoji when followed by the emoji variation selector
+static TEXT_SYMBOLS: &[&str] = &["©", "®", "™"];
+
+lazy_static::lazy_static! {
+    static ref SHORTCODE_REGEX: Regex = Regex::new(r"^:([a-z0-9_+-]+):").unwrap();
+    static ref TAG_REGEX: Regex = Regex::new(r"^<[^<>]*>").unwrap();
+    static ref CUSTOM_EMOJI_REGEX: Regex = Regex::new(r"^<(a?):(\w+):([0-9]+)>$").unwrap();
+    static ref TEXT_EMOJI_REGEX: Regex = Regex::new(r"<a?:(\w+):[0-9]+>|:([a-z0-9_+-]+):").unwrap();
+}
+
+/// Resolves a `:shortcode:`, a bare shortcode, a unicode emoji or a `<:name:id>` custom emoji into its image URL and `:name:`
+pub fn resolve(value: &str, base_url: &str) -> Option<(String, String)> {
+    if let Some(custom) = custom(value) {
+        return Some(custom);
+    }
+    let shortcode = value.strip_prefix(':').and_then(|v| v.strip_suffix(':')).unwrap_or(value);
+    if let Some(emoji) = emojis::get_by_shortcode(shortcode) {
+        return Some((url(emoji, base_url), format!(":{shortcode}:")));
+    }
+    emojis::get(value).map(|emoji| (url(emoji, base_url), name(emoji)))
+}
+
+/// Custom emoji are served by the Discord CDN, animated ones as GIFs
+pub fn custom_url(id: u64, animated: bool) -> String {
+    format!("{}emojis/{}.{}", DISCORD_CDN, id, if animated { "gif" } else { "png" })
+}
+
+/// Replaces `:shortcodes:`, unicode emoji and `<:name:id>` custom emoji with emoji elements, other HTML tags are left untouched
+pub fn replace(text: &str, base_url: &str) -> String {
+    let mut html = String::new();
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        let rest = &text[i..];
+        if let Some(tag) = TAG_REGEX.find(rest).filter(|_| c == '<') {
+            match custom(tag.as_str()) {
+                Some((url, name)) => html.push_str(&element(&name, &url)),
+                None => html.push_str(tag.as_str()),
+            }
+            i += tag.end();
+            continue;
+        }
+        if let Some(captures) = SHORTCODE_REGEX.captures(rest) {
+            if let Some(emoji) = emojis::get_by_shortcode(&captures[1]) {
+                html.push_str(&element(&captures[0], &url(emoji, base_url)));
+                i += captures[0].len();
+                continue;
+            }
+        }
+        if !c.is_ascii() {
+            if let Some((emoji, length)) = leading_emoji(rest) {
+                html.push_str(&element(&name(emoji), &url(emoji, base_url)));
+                i += length;
+                continue;
+            }
+        }
+        html.push(c);
+        i += c.len_utf8();
+    }
+    html
+}
+
+/// Replaces `:shortcodes:` with unicode emoji and `<:name:id>` custom emoji with their `:name:`, for text without images
+pub fn unicode(text: &str) -> String {
+    TEXT_EMOJI_REGEX.replace_all(text, |captures: &Captures| {
+        if let Some(name) = captures.get(1) {
+            return format!(":{}:", name.as_str());
+        }
+        match emojis::get_by_shortcode(&captures[2]) {
+            Some(emoji) => emoji.as_str().to_owned(),
+            None => captures[0].to_owned(),
+        }
+    }).into_owned()
+}
+
+/// Parses Discord's `<:name:id>` and animated `<a:name:id>` custom emoji syntax
+fn custom(value: &str) -> Option<(String, String)> {
+    let captures = CUSTOM_EMOJI_REGEX.captures(value)?;
+    let id = captures[3].parse::<u64>().ok()?;
+    Some((custom_url(id, !captures[1].is_empty()), format!(":{}:", &captures[2])))
+}
+
+/// Longest emoji sequence at the start of `text` and its length in bytes
+fn leading_emoji(text: &str) -> Option<(&'static Emoji, usize)> {
+    let ends = text.char_indices()
+        .skip(1)
+        .map(|(i, _)| i)
+        .chain(iter::once(text.len()))
+        .take(MAX_SEQUENCE_LENGTH)
+        .collect::<Vec<_>>();
+    let (emoji, length) = ends.iter().rev().find_map(|end| emojis::get(&text[..*end]).map(|e| (e, *end)))?;
+    if text[length..].starts_with('\u{fe0e}') || TEXT_SYMBOLS.contains(&&text[..length]) {
+        return None;
+    }
+    Some((emoji, length))
+}
+
+/// Discord names skin tone variants after the default emoji, like `:thumbsup::skin-tone-3:`
+fn name(emoji: &Emoji) -> String {
+    if let Some(shortcode) = emoji.shortcode() {
+        return format!(":{shortcode}:");
+    }
+    let tone = match emoji.skin_tone() {
+        Some(SkinTone::Light) => 1,
+        Some(SkinTone::MediumLight) => 2,
+        Some(SkinTone::Medium) => 3,
+        Some(SkinTone::MediumDark) => 4,
+        Some(SkinTone::Dark) => 5,
+        _ => return emoji.as_str().to_owned(),
+    };
+    match emoji.with_skin_tone(SkinTone::Default).and_then(|e| e.shortcode()) {
+        Some(shortcode) => format!(":{shortcode}::skin-tone-{tone}:"),
+        None => emoji.as_str().to_owned(),
+    }
+}
+
+/// Twemoji names images after the code points, without the variation selector unless the emoji is a ZWJ sequence
+fn url(emoji: &Emoji, base_url: &str) -> String {
+    let text = emoji.as_str();
+    let zwj_sequence = text.contains('\u{200d}');
+    let code = text.chars()
+        .filter(|c| zwj_sequence || *c != '\u{fe0f}')
+        .map(|c| format!("{:x}", c as u32))
+        .collect::<Vec<_>>()
+        .join("-");
+    format!("{}/{code}.svg", base_url.trim_end_matches('/'))
+}
+
+fn element(name: &str, url: &str) -> String {
+    format!("<discord-custom-emoji name=\"{name}\" url=\"{url}\"></discord-custom-emoji>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static BASE_URL: &str = "https://emoji.test/svg/";
+
+    #[test]
+    fn shortcodes_and_unicode_resolve_to_twemoji() {
+        assert_eq!(resolve(":thumbsup:", BASE_URL), Some(("https://emoji.test/svg/1f44d.svg".to_owned(), ":thumbsup:".to_owned())));
+        assert_eq!(resolve("thumbsup", BASE_URL), resolve(":thumbsup:", BASE_URL));
+        assert_eq!(resolve("❤️", BASE_URL), Some(("https://emoji.test/svg/2764.svg".to_owned(), ":heart:".to_owned())));
+        assert_eq!(resolve(":not_an_emoji:", BASE_URL), None);
+    }
+
+    #[test]
+    fn skin_tones_and_sequences_are_named_like_discord() {
+        let (url, name) = resolve("👋🏽", BASE_URL).unwrap();
+        assert_eq!(name, ":wave::skin-tone-3:");
+        assert_eq!(url, "https://emoji.test/svg/1f44b-1f3fd.svg");
+        // ZWJ sequences keep their variation selectors
+        let (url, _) = resolve("🏳️‍🌈", BASE_URL).unwrap();
+        assert_eq!(url, "https://emoji.test/svg/1f3f3-fe0f-200d-1f308.svg");
+    }
+
+    #[test]
+    fn text_is_replaced_around_tags() {
+        let html = replace("Hi :wave: <b title=\":x:\">©</b> 😀", BASE_URL);
+        assert_eq!(html, "Hi <discord-custom-emoji name=\":wave:\" url=\"https://emoji.test/svg/1f44b.svg\"></discord-custom-emoji> \
+            <b title=\":x:\">©</b> <discord-custom-emoji name=\":grinning:\" url=\"https://emoji.test/svg/1f600.svg\"></discord-custom-emoji>");
+        assert_eq!(replace("☺\u{fe0e} :unknown:", BASE_URL), "☺\u{fe0e} :unknown:");
+    }
+
+    #[test]
+    fn custom_emoji_come_from_the_discord_cdn() {
+        assert_eq!(resolve("<:blob:123>", BASE_URL), Some(("https://cdn.discordapp.com/emojis/123.png".to_owned(), ":blob:".to_owned())));
+        assert_eq!(resolve("<a:party:456>", BASE_URL), Some(("https://cdn.discordapp.com/emojis/456.gif".to_owned(), ":party:".to_owned())));
+        assert_eq!(replace("<:blob:123>!", BASE_URL), "<discord-custom-emoji name=\":blob:\" url=\"https://cdn.discordapp.com/emojis/123.png\"></discord-custom-emoji>!");
+        assert_eq!(replace("<:blob:not-an-id>", BASE_URL), "<:blob:not-an-id>");
+    }
+
+    #[test]
+    fn text_gets_unicode_and_names() {
+        assert_eq!(unicode(":wave: <:blob:123> <a:party:456> :unknown:"), "👋 :blob: :party: :unknown:");
+    }
+}
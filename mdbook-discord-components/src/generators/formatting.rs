@@ -0,0 +1,311 @@
+use regex::Regex;
+
+lazy_static::lazy_static! {
+    static ref TAG_REGEX: Regex = Regex::new(r"^<[^<>\s][^<>]*>").unwrap();
+    static ref MASKED_LINK_REGEX: Regex = Regex::new(r"^\[([^\[\]]+)\]\((https?://[^\s()]+)\)").unwrap();
+    static ref URL_REGEX: Regex = Regex::new(r"^https?://[^\s<]+[^\s<.,:;!?)'\x22]").unwrap();
+    static ref ORDERED_ITEM_REGEX: Regex = Regex::new(r"^\s*\d+\.\s").unwrap();
+}
+
+/// Inline formatting delimiters and their elements, longer delimiters have to come first
+static DELIMITERS: &[(&str, &str, &str)] = &[
+    ("**", "<discord-bold>", "</discord-bold>"),
+    ("__", "<discord-underlined>", "</discord-underlined>"),
+    ("~~", "<s>", "</s>"),
+    ("||", "<discord-spoiler>", "</discord-spoiler>"),
+    ("*", "<discord-italic>", "</discord-italic>"),
+    ("_", "<discord-italic>", "</discord-italic>"),
+];
+
+/// Renders Discord flavoured markdown into discord-components elements
+///
+/// Text outside of formatting elements and code goes through `format_text`. HTML tags and
+/// `<...>` mentions are never split by formatting, so they reach `format_text` intact.
+pub fn render(text: &str, format_text: &dyn Fn(&str) -> String) -> String {
+    let lines = text.lines().collect::<Vec<_>>();
+    let mut html = String::new();
+    let mut line_break = false;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        // The info string is the language, which isn't highlighted
+        if line.trim_start().strip_prefix("```").map(|info| !info.contains("```")).unwrap_or(false) {
+            if let Some(end) = lines[i + 1..].iter().position(|l| l.trim_end().ends_with("```")) {
+                let last = lines[i + 1 + end].trim_end();
+                let mut code = lines[i + 1..i + 1 + end].to_vec();
+                code.push(&last[..last.len() - 3]);
+                let code = code.join("\n");
+                // Line breaks are escaped, a blank line would end the HTML block in the chapter
+                html.push_str("<discord-code multiline>");
+                html.push_str(&escape(code.trim_matches('\n')).replace('\n', "&#10;"));
+                html.push_str("</discord-code>");
+                line_break = false;
+                i += end + 2;
+                continue;
+            }
+        }
+        if let Some(rest) = line.strip_prefix(">>> ") {
+            let quoted = std::iter::once(rest).chain(lines[i + 1..].iter().copied()).collect::<Vec<_>>().join("\n");
+            html.push_str("<discord-quote>");
+            html.push_str(&render(&quoted, format_text));
+            html.push_str("</discord-quote>");
+            break;
+        }
+        if is_quote(line) {
+            let count = lines[i..].iter().take_while(|l| is_quote(l)).count();
+            let quoted = lines[i..i + count].iter()
+                .map(|l| l.strip_prefix("> ").unwrap_or(&l[1..]))
+                .collect::<Vec<_>>()
+                .join("\n");
+            html.push_str("<discord-quote>");
+            html.push_str(&render(&quoted, format_text));
+            html.push_str("</discord-quote>");
+            line_break = false;
+            i += count;
+            continue;
+        }
+        if let Some((level, rest)) = header(line) {
+            html.push_str(&format!("<discord-header level=\"{level}\">{}</discord-header>", inline(rest, format_text)));
+            line_break = false;
+            i += 1;
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("-# ") {
+            html.push_str(&format!("<discord-subscript>{}</discord-subscript>", inline(rest, format_text)));
+            line_break = false;
+            i += 1;
+            continue;
+        }
+        if let Some((tag, strip)) = list(line) {
+            let count = lines[i..].iter().take_while(|l| list(l).map(|(t, _)| t == tag).unwrap_or(false)).count();
+            html.push_str(&format!("<{tag}>"));
+            for item in &lines[i..i + count] {
+                html.push_str("<discord-list-item>");
+                html.push_str(&inline(strip(item), format_text));
+                html.push_str("</discord-list-item>");
+            }
+            html.push_str(&format!("</{tag}>"));
+            line_break = false;
+            i += count;
+            continue;
+        }
+        if line_break {
+            html.push_str("<br />");
+        }
+        html.push_str(&inline(line, format_text));
+        line_break = true;
+        i += 1;
+    }
+    html
+}
+
+fn is_quote(line: &str) -> bool {
+    line == ">" || (line.starts_with("> ") && !line.starts_with(">>> "))
+}
+
+fn header(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if !(1..=3).contains(&level) {
+        return None;
+    }
+    line[level..].strip_prefix(' ').map(|rest| (level, rest))
+}
+
+type StripItem = fn(&str) -> &str;
+
+fn list(line: &str) -> Option<(&'static str, StripItem)> {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with("- ") || trimmed.starts_with("* ") {
+        Some(("discord-unordered-list", |l| &l.trim_start()[2..]))
+    } else if ORDERED_ITEM_REGEX.is_match(line) {
+        Some(("discord-ordered-list", |l| ORDERED_ITEM_REGEX.splitn(l, 2).nth(1).unwrap_or_default()))
+    } else {
+        None
+    }
+}
+
+fn inline(text: &str, format_text: &dyn Fn(&str) -> String) -> String {
+    let mut html = String::new();
+    let mut plain = String::new();
+    let flush = |html: &mut String, plain: &mut String| {
+        if !plain.is_empty() {
+            html.push_str(&format_text(plain));
+            plain.clear();
+        }
+    };
+    let mut i = 0;
+    'outer: while let Some(c) = text[i..].chars().next() {
+        let rest = &text[i..];
+        if let Some(skip) = atomic_len(rest) {
+            match c {
+                '\\' => plain.push_str(&rest[1..skip]),
+                '`' => {
+                    let ticks = rest.chars().take_while(|c| *c == '`').count();
+                    flush(&mut html, &mut plain);
+                    html.push_str("<discord-code>");
+                    html.push_str(&escape(rest[ticks..skip - ticks].trim()));
+                    html.push_str("</discord-code>");
+                },
+                _ => plain.push_str(&rest[..skip]),
+            }
+            i += skip;
+            continue;
+        }
+        if let Some(captures) = MASKED_LINK_REGEX.captures(rest) {
+            flush(&mut html, &mut plain);
+            html.push_str(&format!("<discord-link href=\"{}\">{}</discord-link>", escape(&captures[2]), inline(&captures[1], format_text)));
+            i += captures[0].len();
+            continue;
+        }
+        if let Some(url) = URL_REGEX.find(rest).filter(|_| !ends_with_word(&text[..i])) {
+            flush(&mut html, &mut plain);
+            html.push_str(&format!("<discord-link href=\"{0}\">{0}</discord-link>", escape(url.as_str())));
+            i += url.end();
+            continue;
+        }
+        for (delimiter, open, close) in DELIMITERS {
+            if !rest.starts_with(delimiter) {
+                continue;
+            }
+            let length = delimiter.len();
+            if *delimiter == "_" && ends_with_word(&text[..i]) {
+                continue;
+            }
+            let Some(end) = find_closing(text, i + length, delimiter) else {
+                continue;
+            };
+            let inner = &text[i + length..end];
+            if length == 1 && (inner.starts_with(char::is_whitespace) || inner.ends_with(char::is_whitespace)) {
+                continue;
+            }
+            if *delimiter == "_" && text[end + length..].starts_with(|c: char| c.is_alphanumeric()) {
+                continue;
+            }
+            flush(&mut html, &mut plain);
+            html.push_str(open);
+            html.push_str(&inline(inner, format_text));
+            html.push_str(close);
+            i = end + length;
+            continue 'outer;
+        }
+        plain.push(c);
+        i += c.len_utf8();
+    }
+    flush(&mut html, &mut plain);
+    html
+}
+
+/// Length of an escaped character, code span or tag at the start of `text`, which formatting can't split
+fn atomic_len(text: &str) -> Option<usize> {
+    let mut chars = text.chars();
+    match chars.next()? {
+        '\\' => chars.next().filter(|c| c.is_ascii_punctuation()).map(|c| 1 + c.len_utf8()),
+        '`' => {
+            let ticks = text.chars().take_while(|c| *c == '`').count();
+            let delimiter = &text[..ticks];
+            text[ticks..].find(delimiter).filter(|end| *end > 0).map(|end| ticks + end + ticks)
+        },
+        '<' => TAG_REGEX.find(text).map(|m| m.end()),
+        _ => None,
+    }
+}
+
+/// Finds the closing `delimiter`, skipping runs of the delimiter character which belong to other delimiters
+fn find_closing(text: &str, from: usize, delimiter: &str) -> Option<usize> {
+    let delimiter_char = delimiter.chars().next()?;
+    let length = delimiter.len();
+    let mut i = from;
+    // Whether a single delimiter inside, like the `*` of `**bold *italic***`, waits for its closing one
+    let mut single_open = false;
+    while let Some(c) = text[i..].chars().next() {
+        if let Some(skip) = atomic_len(&text[i..]) {
+            i += skip;
+            continue;
+        }
+        if c != delimiter_char {
+            i += c.len_utf8();
+            continue;
+        }
+        let run = text[i..].chars().take_while(|c| *c == delimiter_char).count();
+        match (length, run) {
+            (1, 1) | (2, 2) if i > from => return Some(i),
+            (1, 3..) if i > from => return Some(i + run - 1),
+            // `***both***` closes the outer delimiter after the inner one
+            (2, 3..) if i > from => {
+                return Some(if text[from..].starts_with(delimiter_char) || single_open { i + run - 2 } else { i });
+            },
+            _ => {
+                single_open ^= run == 1;
+                i += run;
+            },
+        }
+    }
+    None
+}
+
+fn ends_with_word(text: &str) -> bool {
+    text.chars().last().map(|c| c.is_alphanumeric()).unwrap_or(false)
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_plain(text: &str) -> String {
+        render(text, &|text| text.to_owned())
+    }
+
+    #[test]
+    fn inline_formatting_nests() {
+        assert_eq!(render_plain("**bold *italic***"), "<discord-bold>bold <discord-italic>italic</discord-italic></discord-bold>");
+        assert_eq!(render_plain("__under__ ~~strike~~ ||spoiler||"), "<discord-underlined>under</discord-underlined> <s>strike</s> <discord-spoiler>spoiler</discord-spoiler>");
+        assert_eq!(render_plain("snake_case_name and * lone stars *"), "snake_case_name and * lone stars *");
+    }
+
+    #[test]
+    fn escapes_and_code_spans_are_literal() {
+        assert_eq!(render_plain("\\*not italic\\*"), "*not italic*");
+        assert_eq!(render_plain("`a **b** <c>`"), "<discord-code>a **b** &lt;c&gt;</discord-code>");
+        assert_eq!(render_plain("``has ` tick``"), "<discord-code>has ` tick</discord-code>");
+    }
+
+    #[test]
+    fn code_blocks_keep_their_lines_on_one() {
+        let html = render_plain("Look\n```rust\nfirst\n\n  second <b>\n```\nDone");
+        assert_eq!(html, "Look<discord-code multiline>first&#10;&#10;  second &lt;b&gt;</discord-code>Done");
+        assert!(!html.contains('\n'));
+    }
+
+    #[test]
+    fn quotes_cover_their_lines() {
+        assert_eq!(render_plain("> one\n> two\nafter"), "<discord-quote>one<br />two</discord-quote>after");
+        assert_eq!(render_plain("before\n>>> all\nthe rest"), "before<discord-quote>all<br />the rest</discord-quote>");
+    }
+
+    #[test]
+    fn lists_headers_and_subtext_are_blocks() {
+        assert_eq!(
+            render_plain("- one\n* two\n1. first\n2. **second**"),
+            "<discord-unordered-list><discord-list-item>one</discord-list-item><discord-list-item>two</discord-list-item></discord-unordered-list>\
+             <discord-ordered-list><discord-list-item>first</discord-list-item><discord-list-item><discord-bold>second</discord-bold></discord-list-item></discord-ordered-list>",
+        );
+        assert_eq!(render_plain("## Title\n-# small"), "<discord-header level=\"2\">Title</discord-header><discord-subscript>small</discord-subscript>");
+        assert_eq!(render_plain("#hashtag"), "#hashtag");
+    }
+
+    #[test]
+    fn links_and_tags_stay_whole() {
+        assert_eq!(
+            render_plain("[site](https://example.com/a_b_c) https://example.com/x."),
+            "<discord-link href=\"https://example.com/a_b_c\">site</discord-link> <discord-link href=\"https://example.com/x\">https://example.com/x</discord-link>.",
+        );
+        assert_eq!(render_plain("*<@user_name>*"), "<discord-italic><@user_name></discord-italic>");
+    }
+}
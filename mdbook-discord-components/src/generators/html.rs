@@ -1,33 +1,85 @@
-use std::collections::HashMap;
+use std::rc::Rc;
 use pulldown_cmark::Event;
 use anyhow::Result;
 
-use super::Generator;
+use super::{formatting, Generator};
 use crate::components::{Components, ComponentTree};
+use crate::config::{Config, Theme};
 
-pub struct HTMLGenerator;
+/// Keeps messages with `theme=auto` in sync with the theme picked in mdBook, which is a class of the `html` element.
+/// Only included with the script tag, `embed-script = false` leaves chapters without scripts
+static THEME_HOOK: &str = r#"<script>
+(() => {
+    const update = () => {
+        const light = ["light", "rust"].some((theme) => document.documentElement.classList.contains(theme));
+        document.querySelectorAll("discord-messages[data-auto-theme]").forEach((messages) => messages.toggleAttribute("light-theme", light));
+    };
+    update();
+    document.addEventListener("DOMContentLoaded", update);
+    new MutationObserver(update).observe(document.documentElement, { attributes: true, attributeFilter: ["class"] });
+})();
+</script>
+"#;
+
+/// Elements whose text content supports Discord's markdown
+static MARKDOWN_ELEMENTS: &[&str] = &["discord-message", "discord-system-message", "discord-embed-description", "discord-embed-field", "discord-reply"];
+
+pub struct HTMLGenerator {
+    config: Rc<Config>,
+}
 
 impl Generator for HTMLGenerator {
-    fn new() -> Self {
-        HTMLGenerator{}
+    fn new(config: Rc<Config>) -> Self {
+        HTMLGenerator{ config }
     }
 
     fn generate<'a>(&self, mut components: Components) -> Result<Event<'a>> {
-        let html = "<discord-messages>\n".to_owned() +
-            &components.tree.drain(..).map(|tree| generate_components(&components.roles, tree, 1)).collect::<String>() +
+        let tree = std::mem::take(&mut components.tree);
+        let display = components.display;
+        let mut attr = match display.theme {
+            Theme::Auto => " data-auto-theme",
+            Theme::Dark => "",
+            Theme::Light => " light-theme",
+        }.to_owned();
+        if display.compact {
+            attr.push_str(" compact-mode");
+        }
+        if display.no_background {
+            attr.push_str(" no-background");
+        }
+        let html = format!("<discord-messages{attr}>\n") +
+            &tree.into_iter().map(|tree| generate_components(&self.config, &components, tree, 1, false)).collect::<String>() +
         "</discord-messages>\n";
         #[cfg(debug_assertions)]
         eprintln!("HTML Generator generated following:\n{html}");
         Ok(Event::Html(html.into()))
     }
+
+    fn head<'a>(&self, path_to_root: &str) -> Option<Event<'a>> {
+        if !self.config.embed_script {
+            return None;
+        }
+        let src = &self.config.script_src;
+        // Like mdBook's `additional-js`, relative paths start at the book root
+        let src = if src.contains("://") || src.starts_with('/') || src.starts_with("data:") {
+            src.to_owned()
+        } else {
+            format!("{path_to_root}{src}")
+        };
+        let integrity = self.config.script_integrity.as_ref()
+            .map(|integrity| format!(" integrity=\"{}\" crossorigin=\"anonymous\"", formatting::escape(integrity)))
+            .unwrap_or_default();
+        Some(Event::Html(format!("<script type=\"module\" src=\"{}\"{integrity}></script>\n{THEME_HOOK}", formatting::escape(&src)).into()))
+    }
 }
 
-fn generate_components(roles: &HashMap<String, String>, component: ComponentTree, indent_size: usize) -> String {
+fn generate_components(config: &Config, components: &Components, component: ComponentTree, indent_size: usize, markdown: bool) -> String {
     let indent = "    ".repeat(indent_size);
     indent.clone() + &match component {
-        ComponentTree::Text(text) => super::format_mentions(roles, text.trim_end_matches("\n").replace("\n", "<br />").to_owned()),
+        ComponentTree::Text(text) => super::format_content(config, components, &text, markdown),
         ComponentTree::Node { data, mut nodes } => {
             let name = data.name().to_owned();
+            let markdown = MARKDOWN_ELEMENTS.contains(&name.as_str());
             let mut attr = data.attrubutes();
             let attr = if attr.is_empty() {
                 String::new()
@@ -37,12 +89,12 @@ fn generate_components(roles: &HashMap<String, String>, component: ComponentTree
                         " ".to_owned() + &if v.is_empty() {
                             k
                         } else {
-                            k + "=\"" + &v +"\""
+                            k + "=\"" + &formatting::escape(&v) +"\""
                         }
                     }).collect::<String>()
             };
             "<".to_owned() + &name + &attr + ">\n" +
-            &nodes.drain(..).map(|n| generate_components(roles, n, indent_size + 1)).collect::<String>() +
+            &nodes.drain(..).map(|n| generate_components(config, components, n, indent_size + 1, markdown)).collect::<String>() +
             &indent + "</" + &name + ">"
         },
     } + "\n"
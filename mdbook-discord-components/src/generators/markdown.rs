@@ -0,0 +1,310 @@
+use std::rc::Rc;
+use pulldown_cmark::Event;
+use regex::{Regex, Captures};
+use anyhow::Result;
+
+use super::{mention_name, sanitize, timestamp, Element, Generator, MENTION_REGEX, REFERENCE_ELEMENTS, SLASH_COMMAND_REGEX};
+use crate::components::{Components, ComponentTree};
+use crate::config::Config;
+use crate::emoji;
+
+lazy_static::lazy_static! {
+    static ref CODE_REGEX: Regex = Regex::new(r"```[\s\S]*?```|``[^`]+``|`[^`]+`").unwrap();
+    static ref MARKDOWN_SPECIAL_REGEX: Regex = Regex::new(r"[\\`*_\[\]<>~|#]").unwrap();
+}
+
+/// Renders messages as plain markdown for renderers which don't run the discord-components script, like `markdown` or `epub`
+pub struct MarkdownGenerator {
+    config: Rc<Config>,
+}
+
+impl Generator for MarkdownGenerator {
+    fn new(config: Rc<Config>) -> Self {
+        MarkdownGenerator{ config }
+    }
+
+    fn generate<'a>(&self, mut components: Components) -> Result<Event<'a>> {
+        let tree = std::mem::take(&mut components.tree);
+        let writer = Writer{ config: &self.config, components: &components };
+        Ok(Event::Text((writer.blocks(tree) + "\n").into()))
+    }
+
+    fn error<'a>(&self, message: &str, code: &str) -> Event<'a> {
+        let markdown = ["**Failed to render discord messages**".to_owned(), code_block(message), code_block(code)].join("\n\n");
+        Event::Text((quote(&markdown) + "\n").into())
+    }
+}
+
+struct Writer<'a> {
+    config: &'a Config,
+    components: &'a Components,
+}
+
+impl Writer<'_> {
+    /// Markdown blocks of the components, separated by blank lines
+    fn blocks(&self, nodes: Vec<ComponentTree>) -> String {
+        nodes.into_iter()
+            .map(|node| self.block(node))
+            .filter(|block| !block.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    fn block(&self, component: ComponentTree) -> String {
+        let (data, nodes) = match component {
+            ComponentTree::Text(text) => return self.text(&text),
+            ComponentTree::Node { data, nodes } => (data, nodes),
+        };
+        let element = Element::new(data, nodes);
+        match element.name.as_str() {
+            "discord-message" => self.message(element),
+            "discord-system-message" => self.system_message(element),
+            "discord-reply" => self.reply(element),
+            "discord-command" => format!(
+                "> **{}** used `{}`",
+                escape(element.get("author").unwrap_or_default()),
+                element.get("command").unwrap_or_default(),
+            ),
+            "discord-embed" => quote(&self.embed(element)),
+            "discord-reactions" => self.reactions(element),
+            "discord-attachment" => format!(
+                "![{}]({})",
+                escape(element.get("alt").unwrap_or_default()),
+                url(element.get("url").unwrap_or_default()),
+            ),
+            "discord-attachments" => self.attachments(element),
+            "discord-invite" => quote(&invite(&element)),
+            _ => self.blocks(element.nodes),
+        }
+    }
+
+    fn message(&self, message: Element) -> String {
+        let mut header = format!("**{}**", escape(message.get("author").unwrap_or_default()));
+        if message.is("bot") {
+            header.push_str(if message.is("verified") { " `✓ BOT`" } else { " `BOT`" });
+        }
+        if let Some(timestamp) = message.get("timestamp") {
+            header.push_str(&format!(" — {}", escape(timestamp)));
+        }
+        if message.is("edited") {
+            header.push_str(" *(edited)*");
+        }
+        if message.is("ephemeral") {
+            header.push_str(" *(only you can see this)*");
+        }
+        let (references, nodes): (Vec<_>, Vec<_>) = message.nodes.into_iter().partition(|node| {
+            matches!(node, ComponentTree::Node { data, .. } if REFERENCE_ELEMENTS.contains(&data.name()))
+        });
+        [self.blocks(references), header, self.blocks(nodes)].into_iter()
+            .filter(|block| !block.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    fn system_message(&self, message: Element) -> String {
+        let mut text = format!("*{}*", self.inline_text(&message.nodes));
+        if let Some(timestamp) = message.get("timestamp") {
+            text.push_str(&format!(" — {}", escape(timestamp)));
+        }
+        text
+    }
+
+    fn reply(&self, reply: Element) -> String {
+        let mut text = self.inline_text(&reply.nodes);
+        if text.is_empty() {
+            text = if reply.is("attachment") { "*Click to see attachment*" } else { "*Click to see original message*" }.to_owned();
+        }
+        format!("> **@{}** {}", escape(reply.get("author").unwrap_or_default()), text)
+    }
+
+    fn embed(&self, mut embed: Element) -> String {
+        let mut blocks = Vec::new();
+        if let Some(name) = embed.get("author-name") {
+            blocks.push(format!("**{}**", link(&escape(name), embed.get("author-url"))));
+        }
+        if let Some(title) = embed.get("embed-title") {
+            blocks.push(format!("**{}**", link(title, embed.get("url"))));
+        }
+        let mut footer = None;
+        for element in Element::children(std::mem::take(&mut embed.nodes)) {
+            match element.name.as_str() {
+                "discord-embed-fields" => for field in Element::children(element.nodes) {
+                    blocks.push(format!("**{}**", field.get("field-title").unwrap_or_default()));
+                    blocks.push(self.blocks(field.nodes));
+                },
+                "discord-embed-footer" => {
+                    let text = self.inline_text(&element.nodes);
+                    footer = Some(match element.get("timestamp") {
+                        Some(timestamp) if !text.is_empty() => format!("{text} • {}", escape(timestamp)),
+                        Some(timestamp) => escape(timestamp),
+                        None => text,
+                    });
+                },
+                _ => blocks.push(self.blocks(element.nodes)),
+            }
+        }
+        for image in ["thumbnail", "image"] {
+            if let Some(image) = embed.get(image) {
+                blocks.push(format!("![]({})", url(image)));
+            }
+        }
+        blocks.extend(footer);
+        blocks.retain(|block| !block.is_empty());
+        blocks.join("\n\n")
+    }
+
+    fn reactions(&self, reactions: Element) -> String {
+        Element::children(reactions.nodes)
+            .map(|reaction| format!(
+                "{} {}",
+                emoji::unicode(reaction.get("name").unwrap_or_default()),
+                reaction.get("count").unwrap_or("1"),
+            ))
+            .collect::<Vec<_>>()
+            .join(" · ")
+    }
+
+    /// Every action row is a list of its buttons, alternating markers keep consecutive rows apart
+    fn attachments(&self, attachments: Element) -> String {
+        let mut blocks = Vec::new();
+        for element in Element::children(attachments.nodes) {
+            match element.name.as_str() {
+                "discord-action-row" => {
+                    let marker = if blocks.len() % 2 == 0 { '-' } else { '*' };
+                    let buttons = Element::children(element.nodes)
+                        .map(|button| format!("{marker} {}", self.button(button)))
+                        .collect::<Vec<_>>();
+                    blocks.push(buttons.join("\n"));
+                },
+                "discord-invite" => blocks.push(quote(&invite(&element))),
+                _ => blocks.push(self.blocks(element.nodes)),
+            }
+        }
+        blocks.retain(|block| !block.is_empty());
+        blocks.join("\n\n")
+    }
+
+    fn button(&self, button: Element) -> String {
+        let label = [
+            button.get("emoji-name").map(emoji::unicode).unwrap_or_default(),
+            escape(&self.inline_text(&button.nodes)),
+        ].into_iter().filter(|part| !part.is_empty()).collect::<Vec<_>>().join(" ");
+        let label = link(&label, button.get("url"));
+        if button.is("disabled") {
+            format!("{label} *(disabled)*")
+        } else {
+            label
+        }
+    }
+
+    /// Text of the nodes on a single line
+    fn inline_text(&self, nodes: &[ComponentTree]) -> String {
+        nodes.iter()
+            .filter_map(|node| match node {
+                ComponentTree::Text(text) => Some(self.text(text)),
+                ComponentTree::Node { .. } => None,
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+            .replace("  \n", " ")
+            .replace('\n', " ")
+    }
+
+    /// Discord's markdown is kept, its markup outside of code is replaced with text
+    /// and line breaks are kept as hard line breaks
+    fn text(&self, text: &str) -> String {
+        let text = text.trim_end_matches('\n');
+        let mut markdown = String::with_capacity(text.len());
+        let mut last = 0;
+        for code in CODE_REGEX.find_iter(text) {
+            markdown.push_str(&self.markup(&text[last..code.start()]));
+            markdown.push_str(code.as_str());
+            last = code.end();
+        }
+        markdown.push_str(&self.markup(&text[last..]));
+        markdown
+    }
+
+    /// Applies the content policy, HTML escaped by it shows as text in the markdown too
+    fn markup(&self, text: &str) -> String {
+        let text = emoji::unicode(&sanitize::sanitize(text, self.config));
+        let text = SLASH_COMMAND_REGEX.replace_all(&text, "`/$1`").replace('\n', "  \n");
+        MENTION_REGEX.replace_all(&text, |captures: &Captures| match &captures[2] {
+            "t:" => timestamp::format(&captures[3], self.config).unwrap_or_else(|| captures[3].to_owned()),
+            "e:" => ":emoji:".to_owned(),
+            "#" => format!("#{}", escape(mention_name(self.config, self.components, "#", &captures[3]))),
+            kind => format!("@{}", escape(mention_name(self.config, self.components, kind, &captures[3]))),
+        }).into_owned()
+    }
+}
+
+fn invite(invite: &Element) -> String {
+    let mut name = format!("**{}**", escape(invite.get("name").unwrap_or_default()));
+    if invite.is("verified") || invite.is("partnered") {
+        name.push_str(" ✓");
+    }
+    format!(
+        "You've been invited to join a server: {name} — {} online, {} members",
+        invite.get("online").unwrap_or("0"),
+        invite.get("members").unwrap_or("0"),
+    )
+}
+
+fn link(text: &str, href: Option<&str>) -> String {
+    match href {
+        Some(href) => format!("[{text}]({})", url(href)),
+        None => text.to_owned(),
+    }
+}
+
+/// Link destinations can't contain spaces or unbalanced parentheses
+fn url(url: &str) -> String {
+    url.replace(' ', "%20").replace('(', "%28").replace(')', "%29")
+}
+
+/// Escapes characters with a meaning in markdown, for names which are shown as written
+fn escape(text: &str) -> String {
+    MARKDOWN_SPECIAL_REGEX.replace_all(text, r"\$0").into_owned()
+}
+
+/// Fenced with more backticks than the code contains in a row
+fn code_block(code: &str) -> String {
+    let longest = code.split(|c| c != '`').map(str::len).max().unwrap_or_default();
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("{fence}\n{}\n{fence}", code.trim_end_matches('\n'))
+}
+
+fn quote(text: &str) -> String {
+    text.lines()
+        .map(|line| if line.is_empty() { ">".to_owned() } else { format!("> {line}") })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::components::message::Message;
+    use crate::config::ContentPolicy;
+    use super::*;
+
+    fn generate(content: &str, config: Config) -> String {
+        let message = ComponentTree::Node {
+            data: Message { author: "Spen".to_owned(), ..Default::default() }.into(),
+            nodes: vec![ComponentTree::Text(content.to_owned())],
+        };
+        let components = Components { tree: vec![message], ..Default::default() };
+        match MarkdownGenerator::new(Rc::new(config)).generate(components).unwrap() {
+            Event::Text(text) => text.into_string(),
+            event => panic!("unexpected event {event:?}"),
+        }
+    }
+
+    #[test]
+    fn content_policy_applies_to_markdown() {
+        let content = "<script>alert(1)</script> <@&1> `<b>`";
+        let escaped = generate(content, Config { content_policy: ContentPolicy::Escape, ..Default::default() });
+        assert!(escaped.contains("&lt;script&gt;alert(1)&lt;/script&gt; @deleted-role `<b>`"), "{escaped}");
+        let raw = generate(content, Config::default());
+        assert!(raw.contains("<script>alert(1)</script>"), "{raw}");
+    }
+}
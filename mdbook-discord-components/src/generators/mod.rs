@@ -1,45 +1,182 @@
-use std::collections::HashMap;
+use std::{
+    any::Any,
+    collections::HashMap,
+    rc::Rc,
+};
 use regex::{Regex, Captures};
 use pulldown_cmark::Event;
 use anyhow::Result;
 
-use crate::components::Components;
+use crate::components::{Components, ComponentTree};
+use crate::config::Config;
+use crate::emoji;
 
 pub mod html;
+pub mod markdown;
+pub mod static_html;
+mod formatting;
+mod sanitize;
+mod timestamp;
+
+/// Name of a slash command in a `</name:id>` mention, a command with up to two subcommands or groups
+static SLASH_COMMAND_NAME: &str = r"[\w-]+(?: [\w-]+){0,2}";
 
 lazy_static::lazy_static! {
-    static ref MENTION_REGEX: Regex = Regex::new("<(!?)(t:|e:|@|#)(.*?)>").unwrap();
+    static ref MENTION_REGEX: Regex = Regex::new("<(!?)(t:|e:|@&|@!|@|#)(.*?)>").unwrap();
+    static ref SLASH_COMMAND_REGEX: Regex = Regex::new(&format!(r"</({SLASH_COMMAND_NAME}):[0-9]+>")).unwrap();
+    static ref EVERYONE_REGEX: Regex = Regex::new(r"(^|[^\w])@(everyone|here)\b").unwrap();
 }
 
 pub trait Generator {
-    fn new() -> Self;
+    fn new(config: Rc<Config>) -> Self;
     fn generate<'a>(&self, components: Components) -> Result<Event<'a>>;
+
+    /// HTML the generated output needs once on the page, like the script defining the elements.
+    /// Relative paths start at `path_to_root`, the book root relative to the chapter
+    fn head<'a>(&self, _path_to_root: &str) -> Option<Event<'a>> {
+        None
+    }
+
+    /// Shown in place of a block which failed to parse, with `on-error = "render"`
+    fn error<'a>(&self, message: &str, code: &str) -> Event<'a> {
+        // Line breaks are escaped, a blank line would end the HTML block
+        let pre = |text: &str| format!(
+            "<pre style=\"margin: 0.5em 0 0; white-space: pre-wrap;\"><code>{}</code></pre>",
+            formatting::escape(text.trim_end_matches('\n')).replace('\n', "&#10;"),
+        );
+        Event::Html(format!(
+            "<div class=\"discord-error\" role=\"alert\" style=\"margin: 1em 0; padding: 0.5em 1em; border-left: 4px solid #ed4245; border-radius: 4px; background: rgba(237, 66, 69, 0.1);\">\n<strong>Failed to render discord messages</strong>\n{}\n{}\n</div>\n",
+            pre(message),
+            pre(code),
+        ).into())
+    }
 }
 
 pub trait Generatable {
     fn name(&self) -> &str;
     fn attrubutes(self: Box<Self>) -> HashMap<String, String>;
+    /// The component as `Any`, for code which inspects components of a known type, like validation rules
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// Formats text written by message authors into discord-components elements, applying the content policy,
+/// emoji and mentions. Discord's markdown is only rendered where the element supports it
+fn format_content(config: &Config, components: &Components, text: &str, markdown: bool) -> String {
+    let format_text = |text: &str| format_mentions(config, components, emoji::replace(&sanitize::sanitize(text, config), &config.emoji_base_url));
+    if markdown {
+        formatting::render(text.trim_end_matches('\n'), &format_text)
+    } else {
+        text.trim_end_matches('\n').split('\n').map(format_text).collect::<Vec<_>>().join("<br />")
+    }
+}
+
+/// Elements shown above the author of a message, like in the client
+static REFERENCE_ELEMENTS: &[&str] = &["discord-reply", "discord-command"];
+
+/// A component with its attributes, for generators which don't map components to elements one to one
+struct Element {
+    name: String,
+    attr: HashMap<String, String>,
+    nodes: Vec<ComponentTree>,
+}
+
+impl Element {
+    fn new(data: Box<dyn Generatable>, nodes: Vec<ComponentTree>) -> Self {
+        Element{ name: data.name().to_owned(), attr: data.attrubutes(), nodes }
+    }
+
+    /// The components among the nodes, text is skipped
+    fn children(nodes: Vec<ComponentTree>) -> impl Iterator<Item = Element> {
+        nodes.into_iter().filter_map(|node| match node {
+            ComponentTree::Node { data, nodes } => Some(Element::new(data, nodes)),
+            ComponentTree::Text(_) => None,
+        })
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.attr.get(key).map(String::as_str)
+    }
+
+    /// Boolean attributes are present without a value, optional ones are `true` or `false`
+    fn is(&self, key: &str) -> bool {
+        self.get(key).map(|value| value != "false").unwrap_or(false)
+    }
 }
 
-fn format_mentions(roles: &HashMap<String, String>, text: String) -> String {
+fn format_mentions(config: &Config, components: &Components, text: String) -> String {
+    let text = EVERYONE_REGEX.replace_all(&text, "$1<discord-mention>$2</discord-mention>");
+    let text = SLASH_COMMAND_REGEX.replace_all(&text, "<discord-mention type=\"slash\">$1</discord-mention>");
     MENTION_REGEX.replace_all(&text, |captures: &Captures| {
         if &captures[2] == "t:" {
-            return format!("<discord-time>{}</discord-time>", &captures[3]);
+            // Anything but Discord's timestamp markup is displayed as written
+            let time = timestamp::format(&captures[3], config).unwrap_or_else(|| captures[3].to_owned());
+            return format!("<discord-time>{}</discord-time>", formatting::escape(&time));
         }
         if &captures[2] == "e:" {
-            return format!("<discord-custom-emoji url=\"{}\"></discord-custom-emoji>", &captures[3]);
+            return format!("<discord-custom-emoji url=\"{}\"></discord-custom-emoji>", formatting::escape(&captures[3]));
         }
-        let attr = if &captures[2] == "#" {
-            " type=\"channel\"".to_owned()
-        } else if roles.contains_key(&captures[3]) {
-            format!(" type=\"role\" color=\"{}\"", roles.get(&captures[3]).unwrap())
-        } else {
-            String::new()
-        } + if &captures[1] == "!" {
+        let id = captures[3].parse::<u64>().ok();
+        let attr = match (&captures[2], id) {
+            ("#", _) => " type=\"channel\"".to_owned(),
+            ("@&", Some(_)) => role_attributes(config.roles.get(&captures[3]).and_then(|role| role.color.as_deref())),
+            (_, Some(_)) => String::new(),
+            (_, None) => components.roles.get(&captures[3]).map(|color| role_attributes(Some(color))).unwrap_or_default(),
+        };
+        let name = mention_name(config, components, &captures[2], &captures[3]);
+        let highlight = if &captures[1] == "!" {
             " highlight"
         } else {
             ""
         };
-        format!("<discord-mention{}>{}</discord-mention>", attr, &captures[3])
+        format!("<discord-mention{}{}>{}</discord-mention>", attr, highlight, formatting::escape(name))
     }).into_owned()
 }
+
+/// Name of a channel, role or user mention, snowflakes are resolved to names and anything else is already a name
+fn mention_name<'a>(config: &'a Config, components: &'a Components, kind: &str, value: &'a str) -> &'a str {
+    let Ok(id) = value.parse::<u64>() else {
+        return value;
+    };
+    match kind {
+        "#" => config.channels.get(value).map(String::as_str).unwrap_or("unknown"),
+        "@&" => config.roles.get(value).map(|role| role.name.as_str()).unwrap_or("deleted-role"),
+        _ => components.users.get(&id).map(String::as_str).unwrap_or("unknown-user"),
+    }
+}
+
+fn role_attributes(color: Option<&str>) -> String {
+    match color {
+        Some(color) => format!(" type=\"role\" color=\"{}\"", formatting::escape(color)),
+        None => " type=\"role\"".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Role;
+    use super::*;
+
+    fn mentions(text: &str) -> String {
+        let config = Config {
+            roles: [("10".to_owned(), Role { name: "Mods".to_owned(), color: Some("#ff0000".to_owned()) })].into(),
+            channels: [("20".to_owned(), "general".to_owned())].into(),
+            ..Default::default()
+        };
+        let components = Components { users: [(30, "Spen".to_owned())].into(), ..Default::default() };
+        format_mentions(&config, &components, text.to_owned())
+    }
+
+    #[test]
+    fn snowflake_mentions_are_resolved() {
+        assert_eq!(mentions("<@30> <@!30> <@31>"), "<discord-mention>Spen</discord-mention> <discord-mention>Spen</discord-mention> <discord-mention>unknown-user</discord-mention>");
+        assert_eq!(mentions("<@&10> <@&11>"), "<discord-mention type=\"role\" color=\"#ff0000\">Mods</discord-mention> <discord-mention type=\"role\">deleted-role</discord-mention>");
+        assert_eq!(mentions("<#20> <#21>"), "<discord-mention type=\"channel\">general</discord-mention> <discord-mention type=\"channel\">unknown</discord-mention>");
+    }
+
+    #[test]
+    fn named_slash_and_everyone_mentions_are_kept() {
+        assert_eq!(mentions("<@Kyle> <!@Kyle>"), "<discord-mention>Kyle</discord-mention> <discord-mention highlight>Kyle</discord-mention>");
+        assert_eq!(mentions("</ban user:123>"), "<discord-mention type=\"slash\">ban user</discord-mention>");
+        assert_eq!(mentions("@everyone and @here, not mail@everyone"), "<discord-mention>everyone</discord-mention> and <discord-mention>here</discord-mention>, not mail@everyone");
+    }
+}
@@ -0,0 +1,107 @@
+use regex::Regex;
+
+use super::SLASH_COMMAND_NAME;
+use super::formatting::escape;
+use crate::config::{Config, ContentPolicy};
+
+static ALLOWED_ATTRIBUTES: &[&str] = &["style", "title"];
+
+lazy_static::lazy_static! {
+    static ref TAG_REGEX: Regex = Regex::new(r"<[^<>]*>").unwrap();
+    /// Discord's own `<...>` syntax of mentions, timestamps and emoji
+    static ref MARKUP_REGEX: Regex = Regex::new(&format!(r"^<(?:!?(?:t:|e:|@|#)[^<>]*|a?:\w+:[0-9]+|/{SLASH_COMMAND_NAME}:[0-9]+)>$")).unwrap();
+    static ref HTML_TAG_REGEX: Regex = Regex::new(r"^<(/?)([a-zA-Z][a-zA-Z0-9-]*)(\s[^<>]*?)?\s*(/?)>$").unwrap();
+    static ref ATTRIBUTE_REGEX: Regex = Regex::new(r#"([^\s"'=<>/]+)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'=<>`]+)))?"#).unwrap();
+}
+
+/// Applies the content policy to text written by message authors, Discord's markup is kept in every policy
+pub fn sanitize(text: &str, config: &Config) -> String {
+    if config.content_policy == ContentPolicy::Raw {
+        return text.to_owned();
+    }
+    let mut html = String::new();
+    let mut last = 0;
+    for tag in TAG_REGEX.find_iter(text) {
+        html.push_str(&escape(&text[last..tag.start()]));
+        last = tag.end();
+        let tag = tag.as_str();
+        if MARKUP_REGEX.is_match(tag) {
+            html.push_str(tag);
+        } else if config.content_policy == ContentPolicy::Allowlist {
+            html.push_str(&allowlisted(tag, &config.allowed_tags).unwrap_or_else(|| escape(tag)));
+        } else {
+            html.push_str(&escape(tag));
+        }
+    }
+    html.push_str(&escape(&text[last..]));
+    html
+}
+
+/// Rebuilds an allowed tag with only the allowed attributes
+fn allowlisted(tag: &str, allowed_tags: &[String]) -> Option<String> {
+    let captures = HTML_TAG_REGEX.captures(tag)?;
+    let name = captures[2].to_ascii_lowercase();
+    if !allowed_tags.iter().any(|t| t.eq_ignore_ascii_case(&name)) {
+        return None;
+    }
+    if !captures[1].is_empty() {
+        return Some(format!("</{name}>"));
+    }
+    let attributes = ATTRIBUTE_REGEX.captures_iter(captures.get(3).map_or("", |m| m.as_str()))
+        .filter(|attribute| ALLOWED_ATTRIBUTES.contains(&attribute[1].to_ascii_lowercase().as_str()))
+        .map(|attribute| {
+            let value = attribute.get(2).or(attribute.get(3)).or(attribute.get(4)).map_or("", |m| m.as_str());
+            format!(" {}=\"{}\"", attribute[1].to_ascii_lowercase(), escape(value))
+        })
+        .collect::<String>();
+    let self_closing = if captures[4].is_empty() { "" } else { " /" };
+    Some(format!("<{name}{attributes}{self_closing}>"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::components::Components;
+    use super::*;
+
+    fn config(content_policy: ContentPolicy) -> Config {
+        Config { content_policy, ..Default::default() }
+    }
+
+    static CONTENT: &str = r#"<b onclick="x()">bold</b> <script>alert(1)</script> <span style="color: red" class='big'>red</span> a < b"#;
+
+    #[test]
+    fn raw_keeps_html() {
+        assert_eq!(sanitize(CONTENT, &config(ContentPolicy::Raw)), CONTENT);
+    }
+
+    #[test]
+    fn allowlist_keeps_allowed_tags_and_attributes() {
+        assert_eq!(
+            sanitize(CONTENT, &config(ContentPolicy::Allowlist)),
+            r#"<b>bold</b> &lt;script&gt;alert(1)&lt;/script&gt; <span style="color: red">red</span> a &lt; b"#,
+        );
+        assert_eq!(sanitize("<BR/><img src=x>", &config(ContentPolicy::Allowlist)), "<br />&lt;img src=x&gt;");
+    }
+
+    #[test]
+    fn escape_keeps_only_discord_markup() {
+        assert_eq!(
+            sanitize(CONTENT, &config(ContentPolicy::Escape)),
+            r#"&lt;b onclick=&quot;x()&quot;&gt;bold&lt;/b&gt; &lt;script&gt;alert(1)&lt;/script&gt; &lt;span style=&quot;color: red&quot; class='big'&gt;red&lt;/span&gt; a &lt; b"#,
+        );
+        let markup = "<@123> <#general> <t:1700000000:R> <:blob:1> <a:party:2> </ban:3>";
+        assert_eq!(sanitize(markup, &config(ContentPolicy::Escape)), markup);
+        // Tags which aren't converted to slash command mentions are escaped like other HTML
+        for policy in [ContentPolicy::Escape, ContentPolicy::Allowlist] {
+            assert_eq!(sanitize("</discord-messages a b c:1>", &config(policy)), "&lt;/discord-messages a b c:1&gt;");
+        }
+    }
+
+    #[test]
+    fn system_message_names_are_bold_with_every_policy() {
+        for policy in [ContentPolicy::Raw, ContentPolicy::Allowlist, ContentPolicy::Escape] {
+            let html = super::super::format_content(&config(policy), &Components::default(), "Spen changed the channel name: **general**", true);
+            assert_eq!(html, "Spen changed the channel name: <discord-bold>general</discord-bold>");
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use std::{collections::HashMap, rc::Rc};
+use pulldown_cmark::Event;
+use regex::{Regex, Captures};
+use anyhow::Result;
+
+use super::{formatting::escape, Element, Generator, REFERENCE_ELEMENTS};
+use crate::components::{Components, ComponentTree};
+use crate::config::{Config, Theme};
+use crate::discord::DISCORD_CDN;
+
+/// Styles of the generated HTML, reproducing the dark and light themes of the Discord client
+static STYLESHEET: &str = include_str!("static_html.css");
+
+lazy_static::lazy_static! {
+    static ref ELEMENT_REGEX: Regex = Regex::new(r#"<(/?)discord-([a-z-]+)((?:\s+[\w-]+(?:="[^"]*")?)*)\s*>"#).unwrap();
+    static ref ATTRIBUTE_REGEX: Regex = Regex::new(r#"([\w-]+)(?:="([^"]*)")?"#).unwrap();
+}
+
+/// Renders messages as plain HTML styled by a bundled stylesheet, so they display without the discord-components script
+pub struct StaticHTMLGenerator {
+    config: Rc<Config>,
+}
+
+impl Generator for StaticHTMLGenerator {
+    fn new(config: Rc<Config>) -> Self {
+        StaticHTMLGenerator{ config }
+    }
+
+    fn generate<'a>(&self, mut components: Components) -> Result<Event<'a>> {
+        let tree = std::mem::take(&mut components.tree);
+        let display = components.display;
+        let mut classes = match display.theme {
+            Theme::Auto => "discord-messages discord-auto-theme",
+            Theme::Dark => "discord-messages",
+            Theme::Light => "discord-messages discord-light-theme",
+        }.to_owned();
+        if display.compact {
+            classes.push_str(" discord-compact");
+        }
+        if display.no_background {
+            classes.push_str(" discord-no-background");
+        }
+        let writer = Writer{ config: &self.config, components: &components };
+        let html = format!("<div class=\"{classes}\">\n") + &writer.nodes(tree, false) + "</div>\n";
+        Ok(Event::Html(html.into()))
+    }
+
+    fn head<'a>(&self, _path_to_root: &str) -> Option<Event<'a>> {
+        Some(Event::Html(format!("<style>\n{STYLESHEET}</style>\n").into()))
+    }
+}
+
+struct Writer<'a> {
+    config: &'a Config,
+    components: &'a Components,
+}
+
+impl Writer<'_> {
+    fn nodes(&self, nodes: Vec<ComponentTree>, markdown: bool) -> String {
+        nodes.into_iter()
+            .map(|node| match node {
+                ComponentTree::Text(text) => self.text(&text, markdown),
+                ComponentTree::Node { data, nodes } => self.element(Element::new(data, nodes)),
+            })
+            .collect()
+    }
+
+    /// The text among the nodes, components are skipped
+    fn texts(&self, nodes: &[ComponentTree], markdown: bool) -> String {
+        nodes.iter()
+            .filter_map(|node| match node {
+                ComponentTree::Text(text) => Some(self.text(text, markdown)),
+                ComponentTree::Node { .. } => None,
+            })
+            .collect()
+    }
+
+    /// Formatted text on a single line, a blank line would end the HTML block in the chapter's markdown
+    fn text(&self, text: &str, markdown: bool) -> String {
+        plain_html(&super::format_content(self.config, self.components, text, markdown)).replace('\n', "&#10;")
+    }
+
+    fn element(&self, element: Element) -> String {
+        match element.name.as_str() {
+            "discord-message" => self.message(element),
+            "discord-system-message" => self.system_message(element),
+            "discord-reply" => self.reply(element),
+            "discord-command" => format!(
+                "<div class=\"discord-reply\">{}{} used <span class=\"discord-command-name\">{}</span></div>\n",
+                avatar("discord-reply-avatar", element.get("avatar")),
+                author("discord-reply-author", &element),
+                escape(element.get("command").unwrap_or_default()),
+            ),
+            "discord-embed" => self.embed(element),
+            "discord-reactions" => format!(
+                "<div class=\"discord-reactions\">{}</div>\n",
+                Element::children(element.nodes).map(|reaction| reaction_html(&reaction)).collect::<String>(),
+            ),
+            "discord-attachment" => {
+                let size = ["width", "height"].iter()
+                    .filter_map(|key| element.get(key).map(|value| format!(" {key}=\"{}\"", escape(value))))
+                    .collect::<String>();
+                format!(
+                    "<img class=\"discord-attachment\" src=\"{}\" alt=\"{}\"{size}>\n",
+                    escape(element.get("url").unwrap_or_default()),
+                    escape(element.get("alt").unwrap_or_default()),
+                )
+            },
+            "discord-attachments" => format!("<div class=\"discord-attachments\">\n{}</div>\n", self.nodes(element.nodes, false)),
+            "discord-action-row" => format!(
+                "<div class=\"discord-action-row\">{}</div>\n",
+                Element::children(element.nodes).map(|button| self.button(button)).collect::<String>(),
+            ),
+            "discord-invite" => invite_html(&element),
+            _ => self.nodes(element.nodes, false),
+        }
+    }
+
+    fn message(&self, message: Element) -> String {
+        let mut classes = "discord-message".to_owned();
+        if message.is("highlight") {
+            classes.push_str(" discord-message-highlight");
+        }
+        if message.is("ephemeral") {
+            classes.push_str(" discord-message-ephemeral");
+        }
+        let avatar = avatar("discord-avatar", message.get("avatar"));
+        let mut header = author("discord-author", &message);
+        if let Some(timestamp) = message.get("timestamp") {
+            header.push_str(&format!("<time class=\"discord-timestamp\">{}</time>", escape(timestamp)));
+        }
+        let edited = if message.is("edited") { "<span class=\"discord-edited\">(edited)</span>" } else { "" };
+        let ephemeral = if message.is("ephemeral") {
+            "<div class=\"discord-ephemeral\">Only you can see this message</div>\n"
+        } else {
+            ""
+        };
+
+        let (references, nodes): (Vec<_>, Vec<_>) = message.nodes.into_iter().partition(|node| {
+            matches!(node, ComponentTree::Node { data, .. } if REFERENCE_ELEMENTS.contains(&data.name()))
+        });
+        let content = self.texts(&nodes, true) + edited;
+        let content = if content.is_empty() {
+            content
+        } else {
+            format!("<div class=\"discord-message-content\">{content}</div>\n")
+        };
+        let accessories = Element::children(nodes).map(|element| self.element(element)).collect::<String>();
+
+        format!(
+            "<div class=\"{classes}\">\n{}{avatar}\n<div class=\"discord-message-body\">\n<div class=\"discord-message-header\">{header}</div>\n{content}{accessories}{ephemeral}</div>\n</div>\n",
+            self.nodes(references, false),
+        )
+    }
+
+    fn system_message(&self, message: Element) -> String {
+        let kind = message.get("type").unwrap_or_default();
+        let icon = match kind {
+            "join" => "→",
+            "leave" => "←",
+            "boost" => "✦",
+            "pin" => "📌",
+            "call" | "missed-call" => "📞",
+            "edit" => "✎",
+            "thread" => "#",
+            "alert" => "⚠",
+            "error" => "✕",
+            _ => "•",
+        };
+        let timestamp = message.get("timestamp")
+            .map(|timestamp| format!("<time class=\"discord-timestamp\">{}</time>", escape(timestamp)))
+            .unwrap_or_default();
+        format!(
+            "<div class=\"discord-system-message discord-system-message-{}\">\n<span class=\"discord-system-message-icon\">{icon}</span>\n<div class=\"discord-message-body\">{}{timestamp}</div>\n</div>\n",
+            escape(kind),
+            self.texts(&message.nodes, true),
+        )
+    }
+
+    fn reply(&self, reply: Element) -> String {
+        let mut content = self.texts(&reply.nodes, true);
+        if content.is_empty() {
+            content = if reply.is("attachment") { "<em>Click to see attachment</em>" } else { "<em>Click to see original message</em>" }.to_owned();
+        }
+        if reply.is("edited") {
+            content.push_str("<span class=\"discord-edited\">(edited)</span>");
+        }
+        let mention = if reply.is("mentions") { "@" } else { "" };
+        let op = if reply.is("op") { "<span class=\"discord-tag\">OP</span>" } else { "" };
+        format!(
+            "<div class=\"discord-reply\">{}{mention}{}{op} <span class=\"discord-reply-content\">{content}</span></div>\n",
+            avatar("discord-reply-avatar", reply.get("avatar")),
+            author("discord-reply-author", &reply),
+        )
+    }
+
+    fn embed(&self, mut embed: Element) -> String {
+        let style = embed.get("color").map(|color| format!(" style=\"border-left-color: {}\"", escape(color))).unwrap_or_default();
+        let mut body = String::new();
+        if let Some(name) = embed.get("author-name") {
+            let image = embed.get("author-image")
+                .map(|image| format!("<img src=\"{}\" alt=\"\">", escape(image)))
+                .unwrap_or_default();
+            body.push_str(&format!("<div class=\"discord-embed-author\">{image}{}</div>\n", link(name, embed.get("author-url"))));
+        }
+        if let Some(title) = embed.get("embed-title") {
+            body.push_str(&format!("<div class=\"discord-embed-title\">{}</div>\n", link(title, embed.get("url"))));
+        }
+        let mut footer = String::new();
+        for element in Element::children(std::mem::take(&mut embed.nodes)) {
+            match element.name.as_str() {
+                "discord-embed-description" => {
+                    body.push_str(&format!("<div class=\"discord-embed-description\">{}</div>\n", self.texts(&element.nodes, true)));
+                },
+                "discord-embed-fields" => {
+                    body.push_str("<div class=\"discord-embed-fields\">\n");
+                    for field in Element::children(element.nodes) {
+                        let inline = if field.is("inline") { " discord-embed-field-inline" } else { "" };
+                        body.push_str(&format!(
+                            "<div class=\"discord-embed-field{inline}\"><div class=\"discord-embed-field-title\">{}</div><div>{}</div></div>\n",
+                            escape(field.get("field-title").unwrap_or_default()),
+                            self.texts(&field.nodes, true),
+                        ));
+                    }
+                    body.push_str("</div>\n");
+                },
+                "discord-embed-footer" => {
+                    let image = element.get("footer-image")
+                        .map(|image| format!("<img src=\"{}\" alt=\"\">", escape(image)))
+                        .unwrap_or_default();
+                    let text = self.texts(&element.nodes, false);
+                    let text = match element.get("timestamp") {
+                        Some(timestamp) if !text.is_empty() => format!("{text} • {}", escape(timestamp)),
+                        Some(timestamp) => escape(timestamp),
+                        None => text,
+                    };
+                    footer = format!("<div class=\"discord-embed-footer\">{image}<span>{text}</span></div>\n");
+                },
+                _ => body.push_str(&self.nodes(element.nodes, false)),
+            }
+        }
+        if let Some(image) = embed.get("image") {
+            body.push_str(&format!("<img class=\"discord-embed-image\" src=\"{}\" alt=\"\">\n", escape(image)));
+        }
+        body.push_str(&footer);
+        let thumbnail = embed.get("thumbnail")
+            .map(|thumbnail| format!("<img class=\"discord-embed-thumbnail\" src=\"{}\" alt=\"\">\n", escape(thumbnail)))
+            .unwrap_or_default();
+        format!("<div class=\"discord-embed\"{style}>\n<div class=\"discord-embed-body\">\n{body}</div>\n{thumbnail}</div>\n")
+    }
+
+    fn button(&self, button: Element) -> String {
+        let class = format!("discord-button discord-button-{}", escape(button.get("type").unwrap_or("secondary")));
+        let emoji = button.get("emoji")
+            .map(|emoji| format!(
+                "<img class=\"discord-emoji\" src=\"{}\" alt=\"{}\">",
+                escape(emoji),
+                escape(button.get("emoji-name").unwrap_or_default()),
+            ))
+            .unwrap_or_default();
+        let label = self.texts(&button.nodes, false);
+        match button.get("url") {
+            Some(url) if !button.is("disabled") => format!(
+                "<a class=\"{class}\" href=\"{}\" target=\"_blank\" rel=\"noopener noreferrer\">{emoji}{label} ↗</a>",
+                escape(url),
+            ),
+            _ => {
+                let disabled = if button.is("disabled") { " disabled" } else { "" };
+                format!("<button type=\"button\" class=\"{class}\"{disabled}>{emoji}{label}</button>")
+            },
+        }
+    }
+}
+
+/// The author name with its role color and bot tag
+fn author(class: &str, element: &Element) -> String {
+    let style = element.get("role-color")
+        .map(|color| format!(" style=\"color: {}\"", escape(color)))
+        .unwrap_or_default();
+    let tag = match (element.is("bot"), element.is("verified")) {
+        (true, true) => "<span class=\"discord-tag\">✓ BOT</span>",
+        (true, false) => "<span class=\"discord-tag\">BOT</span>",
+        _ => "",
+    };
+    format!("<span class=\"{class}\"{style}>{}</span>{tag}", escape(element.get("author").unwrap_or_default()))
+}
+
+/// Authors without an avatar get Discord's default one
+fn avatar(class: &str, url: Option<&str>) -> String {
+    let url = url.map(str::to_owned).unwrap_or_else(|| format!("{DISCORD_CDN}embed/avatars/0.png"));
+    format!("<img class=\"{class}\" src=\"{}\" alt=\"\">", escape(&url))
+}
+
+fn link(text: &str, href: Option<&str>) -> String {
+    match href {
+        Some(href) => format!("<a href=\"{}\" target=\"_blank\" rel=\"noopener noreferrer\">{}</a>", escape(href), escape(text)),
+        None => escape(text),
+    }
+}
+
+fn reaction_html(reaction: &Element) -> String {
+    let reacted = if reaction.is("reacted") { " discord-reaction-reacted" } else { "" };
+    let name = escape(reaction.get("name").unwrap_or_default());
+    let emoji = match reaction.get("emoji") {
+        Some(emoji) => format!("<img class=\"discord-emoji\" src=\"{}\" alt=\"{name}\" title=\"{name}\">", escape(emoji)),
+        None => name,
+    };
+    format!("<span class=\"discord-reaction{reacted}\">{emoji}<span>{}</span></span>", escape(reaction.get("count").unwrap_or("1")))
+}
+
+fn invite_html(invite: &Element) -> String {
+    let name = escape(invite.get("name").unwrap_or_default());
+    let icon = match invite.get("icon") {
+        Some(icon) => format!("<img class=\"discord-invite-icon\" src=\"{}\" alt=\"\">", escape(icon)),
+        None => format!("<span class=\"discord-invite-icon\">{}</span>", escape(&initials(invite.get("name").unwrap_or_default()))),
+    };
+    let badge = if invite.is("verified") {
+        "<span class=\"discord-invite-badge\" title=\"Verified\">✓</span>"
+    } else if invite.is("partnered") {
+        "<span class=\"discord-invite-badge\" title=\"Partnered\">∞</span>"
+    } else {
+        ""
+    };
+    format!(
+        "<div class=\"discord-invite\">\n<div class=\"discord-invite-header\">You've been invited to join a server</div>\n<div class=\"discord-invite-body\">{icon}<div class=\"discord-invite-info\"><div class=\"discord-invite-name\">{badge}{name}</div><div class=\"discord-invite-counts\"><span class=\"discord-invite-online\">{} Online</span><span class=\"discord-invite-members\">{} Members</span></div></div><span class=\"discord-button discord-button-success\">Join</span></div>\n</div>\n",
+        escape(invite.get("online").unwrap_or("0")),
+        escape(invite.get("members").unwrap_or("0")),
+    )
+}
+
+/// Servers without an icon show the initials of their name, like `Discord Developers` becomes `DD`
+fn initials(name: &str) -> String {
+    name.split_whitespace().filter_map(|word| word.chars().next()).collect()
+}
+
+/// Replaces the discord-components elements of formatted text with plain HTML elements
+fn plain_html(html: &str) -> String {
+    // Closing tags of the open elements, which depend on the attributes of the opening tag
+    let mut open = Vec::<(String, &str)>::new();
+    ELEMENT_REGEX.replace_all(html, |captures: &Captures| {
+        let name = &captures[2];
+        if !captures[1].is_empty() {
+            let index = open.iter().rposition(|(open, _)| open == name);
+            return index.map(|i| open.remove(i).1.to_owned()).unwrap_or_default();
+        }
+        let attr = ATTRIBUTE_REGEX.captures_iter(&captures[3])
+            .map(|attribute| (attribute[1].to_owned(), attribute.get(2).map_or("", |m| m.as_str()).to_owned()))
+            .collect::<HashMap<_, _>>();
+        // Attribute values are already escaped
+        let get = |key: &str| attr.get(key).map(String::as_str).unwrap_or_default();
+        let (opening, closing) = match name {
+            "bold" => ("<strong>".to_owned(), "</strong>"),
+            "italic" => ("<em>".to_owned(), "</em>"),
+            "underlined" => ("<u>".to_owned(), "</u>"),
+            "spoiler" => ("<span class=\"discord-spoiler\" tabindex=\"0\">".to_owned(), "</span>"),
+            "code" if attr.contains_key("multiline") => ("<pre class=\"discord-code-block\"><code>".to_owned(), "</code></pre>"),
+            "code" => ("<code class=\"discord-code\">".to_owned(), "</code>"),
+            "header" => (format!("<span class=\"discord-header discord-header-{}\">", get("level")), "</span>"),
+            "subscript" => ("<small class=\"discord-subscript\">".to_owned(), "</small>"),
+            "link" => (format!("<a href=\"{}\" target=\"_blank\" rel=\"noopener noreferrer\">", get("href")), "</a>"),
+            "unordered-list" => ("<ul class=\"discord-list\">".to_owned(), "</ul>"),
+            "ordered-list" => ("<ol class=\"discord-list\">".to_owned(), "</ol>"),
+            "list-item" => ("<li>".to_owned(), "</li>"),
+            "quote" => ("<blockquote class=\"discord-quote\">".to_owned(), "</blockquote>"),
+            "time" => ("<time class=\"discord-time\">".to_owned(), "</time>"),
+            "custom-emoji" => (format!("<img class=\"discord-emoji\" src=\"{0}\" alt=\"{1}\" title=\"{1}\">", get("url"), get("name")), ""),
+            "mention" => {
+                let style = attr.get("color").map(|color| format!(" style=\"--discord-role-color: {color}\"")).unwrap_or_default();
+                let prefix = match get("type") {
+                    "channel" => "#",
+                    "slash" => "/",
+                    _ => "@",
+                };
+                (format!("<span class=\"discord-mention\"{style}>{prefix}"), "</span>")
+            },
+            _ => (format!("<span class=\"discord-{name}\">"), "</span>"),
+        };
+        open.push((name.to_owned(), closing));
+        opening
+    }).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formatting_elements_become_plain_html() {
+        assert_eq!(
+            plain_html("<discord-unordered-list><discord-list-item><discord-bold>one</discord-bold></discord-list-item></discord-unordered-list>"),
+            "<ul class=\"discord-list\"><li><strong>one</strong></li></ul>",
+        );
+        assert_eq!(
+            plain_html("<discord-ordered-list><discord-list-item>first</discord-list-item></discord-ordered-list>"),
+            "<ol class=\"discord-list\"><li>first</li></ol>",
+        );
+        assert_eq!(
+            plain_html("<discord-code multiline>a&#10;b</discord-code><discord-mention type=\"channel\">general</discord-mention>"),
+            "<pre class=\"discord-code-block\"><code>a&#10;b</code></pre><span class=\"discord-mention\">#general</span>",
+        );
+    }
+}
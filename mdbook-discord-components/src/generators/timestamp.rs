@@ -0,0 +1,150 @@
+use std::env;
+use chrono::{Locale, TimeZone, Utc};
+use pure_rust_locales::locale_match;
+
+use crate::config::Config;
+
+/// Formats Discord's `<t:unix:style>` timestamp markup, without the `<t:` and `>`, like the client does
+///
+/// Date and time layouts follow the configured locale, relative timestamps are measured against the configured `now`.
+/// Returns `None` when the markup isn't a unix timestamp with a known style.
+pub fn format(markup: &str, config: &Config) -> Option<String> {
+    let (seconds, style) = markup.split_once(':').unwrap_or((markup, "f"));
+    let seconds = seconds.parse::<i64>().ok()?;
+    let time = config.timezone.timestamp_opt(seconds, 0).single()?;
+    let locale = config.locale;
+    let pattern = match style {
+        "t" => short_time(locale),
+        "T" => long_time(locale),
+        "d" => short_date(locale),
+        "D" => long_date(locale),
+        "f" => format!("{} {}", long_date(locale), short_time(locale)),
+        "F" => format!("%A, {} {}", long_date(locale), short_time(locale)),
+        "R" => {
+            return Some(relative(seconds - now(config)));
+        },
+        _ => return None,
+    };
+    Some(time.format_localized(&pattern, locale).to_string())
+}
+
+/// Unix time relative timestamps are measured against: the configured `now`, `SOURCE_DATE_EPOCH` for reproducible builds, or the current time
+fn now(config: &Config) -> i64 {
+    config.now.map(|now| now.timestamp())
+        .or_else(|| env::var("SOURCE_DATE_EPOCH").ok().and_then(|epoch| epoch.trim().parse().ok()))
+        .unwrap_or_else(|| Utc::now().timestamp())
+}
+
+fn twelve_hour_clock(locale: Locale) -> bool {
+    let time_format = locale_match!(locale => LC_TIME::T_FMT);
+    ["%I", "%l", "%r"].iter().any(|f| time_format.contains(f))
+}
+
+fn short_time(locale: Locale) -> String {
+    if twelve_hour_clock(locale) { "%-I:%M %p" } else { "%H:%M" }.to_owned()
+}
+
+fn long_time(locale: Locale) -> String {
+    if twelve_hour_clock(locale) { "%-I:%M:%S %p" } else { "%H:%M:%S" }.to_owned()
+}
+
+/// The locale's numeric date, always with a four digit year like the client
+fn short_date(locale: Locale) -> String {
+    locale_match!(locale => LC_TIME::D_FMT).replace("%y", "%Y")
+}
+
+/// Month name date, ordered like the locale's numeric date
+fn long_date(locale: Locale) -> String {
+    let date_format = locale_match!(locale => LC_TIME::D_FMT);
+    let position = |specifiers: &[&str]| specifiers.iter().filter_map(|s| date_format.find(s)).min().unwrap_or(usize::MAX);
+    let (day, month, year) = (position(&["%d", "%e"]), position(&["%m", "%b", "%B"]), position(&["%y", "%Y", "%C", "%F"]));
+    if year < day && year < month {
+        "%Y %B %-d".to_owned()
+    } else if month < day {
+        "%B %-d, %Y".to_owned()
+    } else if date_format.contains('.') {
+        "%-d. %B %Y".to_owned()
+    } else {
+        "%-d %B %Y".to_owned()
+    }
+}
+
+/// Relative time in the rounded style of the Discord client, like `in 5 minutes` or `a month ago`
+fn relative(seconds: i64) -> String {
+    let distance = seconds.unsigned_abs() as f64;
+    let minutes = distance / 60.0;
+    let hours = minutes / 60.0;
+    let days = hours / 24.0;
+    let amount = if distance < 45.0 {
+        "a few seconds".to_owned()
+    } else if distance < 90.0 {
+        "a minute".to_owned()
+    } else if minutes < 45.0 {
+        format!("{} minutes", minutes.round())
+    } else if minutes < 90.0 {
+        "an hour".to_owned()
+    } else if hours < 22.0 {
+        format!("{} hours", hours.round())
+    } else if hours < 36.0 {
+        "a day".to_owned()
+    } else if days < 26.0 {
+        format!("{} days", days.round())
+    } else if days < 45.0 {
+        "a month".to_owned()
+    } else if days < 320.0 {
+        format!("{} months", (days / 30.4).round().max(2.0))
+    } else if days < 548.0 {
+        "a year".to_owned()
+    } else {
+        format!("{} years", (days / 365.25).round().max(2.0))
+    };
+    if seconds < 0 {
+        format!("{amount} ago")
+    } else {
+        format!("in {amount}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use super::*;
+
+    fn config(timezone: &str, locale: Locale) -> Config {
+        Config {
+            now: Some(DateTime::parse_from_rfc3339("2023-11-14T22:13:20Z").unwrap()),
+            timezone: timezone.parse().unwrap(),
+            locale,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn styles_follow_the_locale() {
+        let us = config("UTC", Locale::en_US);
+        let formatted = ["t", "T", "d", "D", "f", "F"].map(|style| format(&format!("1700000000:{style}"), &us).unwrap());
+        assert_eq!(formatted, ["10:13 PM", "10:13:20 PM", "11/14/2023", "November 14, 2023", "November 14, 2023 10:13 PM", "Tuesday, November 14, 2023 10:13 PM"]);
+        assert_eq!(format("1700000000", &us), format("1700000000:f", &us));
+
+        let de = config("Europe/Berlin", Locale::de_DE);
+        assert_eq!(format("1700000000:f", &de).unwrap(), "14. November 2023 23:13");
+        assert_eq!(format("1700000000:d", &de).unwrap(), "14.11.2023");
+    }
+
+    #[test]
+    fn relative_timestamps_are_measured_against_now() {
+        let config = config("UTC", Locale::en_US);
+        assert_eq!(format("1699999990:R", &config).unwrap(), "a few seconds ago");
+        assert_eq!(format("1700000300:R", &config).unwrap(), "in 5 minutes");
+        assert_eq!(format("1699913600:R", &config).unwrap(), "a day ago");
+        assert_eq!(format("1694816000:R", &config).unwrap(), "2 months ago");
+        assert_eq!(format("1636928000:R", &config).unwrap(), "2 years ago");
+    }
+
+    #[test]
+    fn other_markup_is_not_a_timestamp() {
+        let config = config("UTC", Locale::en_US);
+        assert_eq!(format("tomorrow", &config), None);
+        assert_eq!(format("1700000000:X", &config), None);
+    }
+}
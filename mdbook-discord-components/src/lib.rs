@@ -1,5 +1,8 @@
-#[cfg(feature = "http")]
 mod discord;
 pub mod parsers;
 pub mod generators;
-mod components;
+pub mod components;
+pub mod validation;
+pub mod schema;
+pub mod config;
+mod emoji;
@@ -1,17 +1,29 @@
-use std::{io, process};
-use clap::{Arg, Command};
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process,
+    rc::Rc,
+};
+use clap::{builder::PossibleValuesParser, Arg, ArgAction, Command};
 use mdbook::{
     preprocess::{CmdPreprocessor, Preprocessor},
     errors::Error,
+    Config,
 };
-use preprocessor::DiscordComponentsPreprocessor;
+use preprocessor::{DiscordComponentsPreprocessor, Format};
+use parsers::{YamlParser, PARSER_NAMES};
 
-#[cfg(feature = "http")]
 mod discord;
 mod preprocessor;
 mod parsers;
 mod generators;
 mod components;
+mod validation;
+mod schema;
+mod config;
+mod emoji;
+mod assets;
 
 fn main() {
     let matches = Command::new("discord-components-preprocessor")
@@ -20,6 +32,28 @@ fn main() {
             Command::new("supports")
                 .arg(Arg::new("renderer").required(true))
                 .about("Check whether a renderer is supported by this preprocessor"),
+        )
+        .subcommand(
+            Command::new("install")
+                .arg(Arg::new("dir").default_value("."))
+                .about("Install the bundled discord-components script into the source directory of the book in dir"),
+        )
+        .subcommand(
+            Command::new("render")
+                .arg(Arg::new("file").required(true).help("File of messages, or - to read them from stdin"))
+                .arg(Arg::new("format").long("format").value_parser(["html", "static-html", "markdown"]).default_value("html")
+                    .help("discord-components elements, static HTML and CSS, or markdown"))
+                .arg(Arg::new("parser").long("parser").value_parser(PossibleValuesParser::new(PARSER_NAMES))
+                    .help("Parser of the messages, by default picked by the file extension like for {{#discord}}"))
+                .arg(Arg::new("standalone").long("standalone").action(ArgAction::SetTrue)
+                    .help("Write a complete HTML page, with the script or stylesheet in its head"))
+                .arg(Arg::new("book").long("book")
+                    .help("Directory of a book to take the [preprocessor.discord-components] configuration from"))
+                .about("Render a file of messages outside of mdBook, like a discord block of a chapter"),
+        )
+        .subcommand(
+            Command::new("schema")
+                .about("Print the JSON Schema of the messages of yaml blocks, for editors to complete and check them"),
         ).get_matches();
 
     let preprocessor = DiscordComponentsPreprocessor;
@@ -28,8 +62,20 @@ fn main() {
         if !preprocessor.supports_renderer(arg.get_one::<String>("renderer").expect("Required argument")) {
             process::exit(2);
         }
+    } else if let Some(arg) = matches.subcommand_matches("install") {
+        if let Err(err) = handle_install(Path::new(arg.get_one::<String>("dir").expect("Has a default value"))) {
+            eprintln!("{err:#}");
+            process::exit(1);
+        }
+    } else if let Some(arg) = matches.subcommand_matches("render") {
+        if let Err(err) = handle_render(&preprocessor, arg) {
+            eprintln!("{err:#}");
+            process::exit(1);
+        }
+    } else if matches.subcommand_matches("schema").is_some() {
+        println!("{}", serde_json::to_string_pretty(&YamlParser::schema()).expect("The schema is valid JSON"));
     } else if let Err(err) = handle_preprocessing(&preprocessor) {
-        eprintln!("{err}");
+        eprintln!("{err:#}");
         process::exit(1);
     }
     process::exit(0);
@@ -54,3 +100,37 @@ fn handle_preprocessing(pre: &dyn Preprocessor) -> Result<(), Error> {
 
     Ok(())
 }
+
+fn handle_install(root: &Path) -> Result<(), Error> {
+    let config = Config::from_disk(root.join("book.toml"))?;
+    let path = assets::install_script(&root.join(&config.book.src))?;
+    println!("Installed {}", path.display());
+    println!("Load it with `script-src = \"{}\"` in the [preprocessor.discord-components] table of book.toml", assets::SCRIPT_FILE_NAME);
+    Ok(())
+}
+
+fn handle_render(pre: &DiscordComponentsPreprocessor, arg: &clap::ArgMatches) -> Result<(), Error> {
+    let file = arg.get_one::<String>("file").expect("Required argument");
+    let (code, path) = if file == "-" {
+        let mut code = String::new();
+        io::stdin().read_to_string(&mut code)?;
+        (code, None)
+    } else {
+        let path = PathBuf::from(file);
+        (fs::read_to_string(&path).map_err(|err| Error::new(err).context(format!("Failed to read '{file}'")))?, Some(path))
+    };
+    let config = match arg.get_one::<String>("book") {
+        Some(root) => preprocessor::load_config(&Config::from_disk(Path::new(root).join("book.toml"))?, Path::new(root))?,
+        None => Default::default(),
+    };
+    let parser = arg.get_one::<String>("parser").map(String::as_str)
+        .unwrap_or_else(|| path.as_deref().map(preprocessor::parser_for_path).unwrap_or("yaml"));
+    let format = match arg.get_one::<String>("format").map(String::as_str) {
+        Some("static-html") => Format::StaticHtml,
+        Some("markdown") => Format::Markdown,
+        _ => Format::Html,
+    };
+    let standalone = arg.get_flag("standalone");
+    print!("{}", pre.render(&code, parser, path, &Rc::new(config), format, standalone)?);
+    Ok(())
+}
@@ -0,0 +1,318 @@
+use std::{
+    fmt::Display,
+    error::Error,
+};
+use serde_yaml::{Mapping, Value};
+use anyhow::Result;
+
+use super::{DiscordCodeBlock, Parser, YamlParser};
+use crate::components::Components;
+use crate::config::Config;
+use crate::validation::Problem;
+
+static MESSAGE_FLAGS: Flags = Flags {
+    booleans: &["bot", "verified", "edited", "ephemeral", "highlight"],
+    strings: &["as", "color", "avatar", "timestamp"],
+};
+
+static REPLY_FLAGS: Flags = Flags {
+    booleans: &["bot", "verified", "mentions", "op", "attachment", "edited", "command"],
+    strings: &["as", "color", "avatar"],
+};
+
+static SYSTEM_FLAGS: Flags = Flags {
+    booleans: &["channel_name"],
+    strings: &["timestamp"],
+};
+
+/// Parses a compact chat transcript, desugaring it into the YAML message model
+///
+/// ```text
+/// > Spen: !echo
+/// Wiki Bot [bot, verified, color=#b9a0e0]: Hello
+///   indented lines continue the previous message
+/// --- join: Welcome, Snazzah. We hope you brought pizza.
+/// ```
+pub struct ChatParser;
+
+impl Parser for ChatParser {
+    fn new() -> Self { Self{} }
+
+    fn parse<P: Parser>(&self, code_block: &DiscordCodeBlock<P>) -> Result<Components> {
+        let messages = parse_transcript(&code_block.code, &code_block.config)?;
+        YamlParser::parse_value(Value::Sequence(messages), code_block)
+    }
+
+    /// Problems are located at the header line of their message, the content at its start in the line
+    fn locate<P: Parser>(&self, code_block: &DiscordCodeBlock<P>, problem: &Problem) -> Option<(Option<String>, usize, usize)> {
+        let (i, line) = code_block.code.lines().enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with([' ', '\t', '>']))
+            .nth(problem.index)?;
+        let column = match problem.field.as_deref() {
+            Some("content") => Header::parse(line.strip_prefix("---").unwrap_or(line), i + 1).ok()
+                .map(|header| line[..line.trim_end().len() - header.content.len()].chars().count() + 1)
+                .unwrap_or(1),
+            _ => 1,
+        };
+        Some((problem.field.clone(), i + 1, column))
+    }
+}
+
+#[derive(PartialEq)]
+enum Last {
+    None,
+    Message,
+    Reply,
+}
+
+fn parse_transcript(code: &str, config: &Config) -> Result<Vec<Value>> {
+    let mut messages = Vec::<Value>::new();
+    let mut reply: Option<Mapping> = None;
+    let mut last = Last::None;
+    let mut blank_lines = 0;
+    // Continuation lines lose the indent of the first one, deeper indents like those of code are kept
+    let mut continuation_indent = None;
+    for (i, line) in code.lines().enumerate() {
+        let line_number = i + 1;
+        if line.trim().is_empty() {
+            blank_lines += 1;
+            continue;
+        }
+        if line.starts_with([' ', '\t']) {
+            let target = match last {
+                Last::Message => messages.last_mut().and_then(|m| m.as_mapping_mut()),
+                Last::Reply => reply.as_mut(),
+                Last::None => return Err(ChatParserError::new(line_number, "continuation line without a message").anyhow()),
+            };
+            let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+            let strip = indent.min(*continuation_indent.get_or_insert(indent));
+            if let Some(Value::String(content)) = target.and_then(|t| t.get_mut("content")) {
+                // Content may start on the line after the header
+                if !content.is_empty() {
+                    content.push_str(&"\n".repeat(blank_lines + 1));
+                }
+                content.push_str(line[strip..].trim_end());
+            }
+            blank_lines = 0;
+            continue;
+        }
+        blank_lines = 0;
+        continuation_indent = None;
+
+        if let Some(rest) = line.strip_prefix("---") {
+            if reply.is_some() {
+                return Err(ChatParserError::new(line_number, "a system message can't be a reply").anyhow());
+            }
+            let header = Header::parse(rest, line_number)?;
+            let mut message = header.flags_into(&SYSTEM_FLAGS, line_number)?;
+            message.insert("type".into(), header.name.into());
+            message.insert("content".into(), header.content.into());
+            messages.push(Value::Mapping(message));
+            last = Last::Message;
+        } else if let Some(rest) = line.strip_prefix('>') {
+            if reply.is_some() {
+                return Err(ChatParserError::new(line_number, "a message can only reply to a single message").anyhow());
+            }
+            let header = Header::parse(rest, line_number)?;
+            let mut message = header.flags_into(&REPLY_FLAGS, line_number)?;
+            message.insert(header.author_key(config, "author"), header.name.into());
+            message.insert("content".into(), header.content.into());
+            reply = Some(message);
+            last = Last::Reply;
+        } else {
+            let header = Header::parse(line, line_number)?;
+            let mut message = header.flags_into(&MESSAGE_FLAGS, line_number)?;
+            message.insert(header.author_key(config, "username"), header.name.into());
+            message.insert("content".into(), header.content.into());
+            if let Some(reply) = reply.take() {
+                message.insert("reply".into(), Value::Mapping(reply));
+            }
+            messages.push(Value::Mapping(message));
+            last = Last::Message;
+        }
+    }
+    if reply.is_some() {
+        return Err(ChatParserError::new(code.lines().count(), "reply marker without a following message").anyhow());
+    }
+    Ok(messages)
+}
+
+/// A `Name [flag, key=value]: content` line
+struct Header {
+    name: String,
+    flags: Vec<(String, Option<String>)>,
+    content: String,
+}
+
+impl Header {
+    fn parse(line: &str, line_number: usize) -> Result<Self> {
+        let Some(end) = line.find([':', '[']) else {
+            return Err(ChatParserError::new(line_number, "expected a line in the 'Author: content' format").anyhow());
+        };
+        let name = line[..end].trim().to_owned();
+        if name.is_empty() {
+            return Err(ChatParserError::new(line_number, "missing author").anyhow());
+        }
+        let mut rest = &line[end..];
+        let mut flags = Vec::new();
+        if let Some(inner) = rest.strip_prefix('[') {
+            let Some(close) = inner.find(']') else {
+                return Err(ChatParserError::new(line_number, "unclosed flag list").anyhow());
+            };
+            for flag in inner[..close].split(',') {
+                match flag.split_once('=') {
+                    Some((key, value)) => flags.push((key.trim().to_owned(), Some(value.trim().to_owned()))),
+                    None => flags.extend(flag.split_whitespace().map(|f| (f.to_owned(), None))),
+                }
+            }
+            rest = inner[close + 1..].trim_start();
+        }
+        let Some(content) = rest.strip_prefix(':') else {
+            return Err(ChatParserError::new(line_number, "expected ':' after the author").anyhow());
+        };
+        Ok(Self { name, flags, content: content.trim().to_owned() })
+    }
+
+    /// Authors named like a persona reference it instead of setting their name
+    fn author_key(&self, config: &Config, name_key: &str) -> Value {
+        if config.personas.contains_key(&self.name) {
+            "as".into()
+        } else {
+            name_key.into()
+        }
+    }
+
+    fn flags_into(&self, allowed: &Flags, line_number: usize) -> Result<Mapping> {
+        let mut mapping = Mapping::new();
+        for (key, value) in &self.flags {
+            let value = if allowed.booleans.contains(&key.as_str()) {
+                match value.as_deref() {
+                    None | Some("true") => Value::Bool(true),
+                    Some("false") => Value::Bool(false),
+                    Some(v) => return Err(ChatParserError::new(line_number, format!("flag '{key}' expects true or false, found '{v}'")).anyhow()),
+                }
+            } else if allowed.strings.contains(&key.as_str()) {
+                match value {
+                    Some(v) => Value::String(v.clone()),
+                    None => return Err(ChatParserError::new(line_number, format!("flag '{key}' requires a value")).anyhow()),
+                }
+            } else if key == "user_id" {
+                match value.as_deref().map(str::parse::<u64>) {
+                    Some(Ok(id)) => Value::Number(id.into()),
+                    _ => return Err(ChatParserError::new(line_number, "flag 'user_id' requires a numeric value").anyhow()),
+                }
+            } else {
+                return Err(ChatParserError::new(line_number, format!("unknown flag '{key}'")).anyhow());
+            };
+            mapping.insert(key.as_str().into(), value);
+        }
+        Ok(mapping)
+    }
+}
+
+struct Flags {
+    booleans: &'static [&'static str],
+    strings: &'static [&'static str],
+}
+
+#[derive(Debug)]
+struct ChatParserError {
+    line: usize,
+    message: String,
+}
+
+impl ChatParserError {
+    fn new<S: Into<String>>(line: usize, message: S) -> Self {
+        Self { line, message: message.into() }
+    }
+
+    fn anyhow(self) -> anyhow::Error {
+        anyhow::Error::new(self)
+    }
+}
+
+impl Display for ChatParserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ChatParserError {}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use crate::parsers::Diagnostic;
+    use super::*;
+
+    fn parse(code: &str) -> Result<Vec<Value>> {
+        parse_transcript(code, &Config::default())
+    }
+
+    fn content(message: &Value) -> &str {
+        message["content"].as_str().unwrap()
+    }
+
+    #[test]
+    fn headers_become_messages() {
+        let messages = parse("> Spen [op]: !echo\nWiki Bot [bot, verified, color=#b9a0e0]: Hello\n--- join: Welcome").unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["username"], "Wiki Bot");
+        assert_eq!(messages[0]["bot"], true);
+        assert_eq!(messages[0]["color"], "#b9a0e0");
+        assert_eq!(messages[0]["reply"]["author"], "Spen");
+        assert_eq!(messages[0]["reply"]["op"], true);
+        assert_eq!(content(&messages[0]["reply"]), "!echo");
+        assert_eq!(messages[1]["type"], "join");
+        assert_eq!(content(&messages[1]), "Welcome");
+    }
+
+    #[test]
+    fn continuation_lines_keep_the_indent_of_code() {
+        let messages = parse("Spen: Look\n  ```rust\n  fn main() {\n      println!(\"Hi\");\n\n  }\n  ```\n Less indented").unwrap();
+        assert_eq!(content(&messages[0]), "Look\n```rust\nfn main() {\n    println!(\"Hi\");\n\n}\n```\nLess indented");
+    }
+
+    #[test]
+    fn content_can_start_on_a_continuation_line() {
+        let messages = parse("Spen:\n  Hello\n\n  there\n> Kyle:\n  Hey\nWiki Bot: Hi").unwrap();
+        assert_eq!(content(&messages[0]), "Hello\n\nthere");
+        assert_eq!(content(&messages[1]["reply"]), "Hey");
+    }
+
+    #[test]
+    fn personas_are_referenced_by_name() {
+        let config = Config { personas: [("Spen".to_owned(), Default::default())].into(), ..Default::default() };
+        let messages = parse_transcript("Spen: Hi\nKyle: Hey", &config).unwrap();
+        assert_eq!(messages[0]["as"], "Spen");
+        assert_eq!(messages[1]["username"], "Kyle");
+    }
+
+    #[test]
+    fn unknown_personas_point_at_their_message() {
+        let mut block = DiscordCodeBlock::<ChatParser>::new("discord chat".to_owned(), "Test".to_owned(), Rc::new(Config::default()));
+        block.push_code("Spen: Hi\n\nKyle [as=kyle]: Hey\n");
+        let err = ChatParser.parse(&block).err().unwrap();
+        assert_eq!(err.to_string(), "as: invalid message: unknown persona 'kyle'");
+        assert_eq!(err.downcast_ref::<Diagnostic>().unwrap().line, 3);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = |code| parse(code).err().unwrap().to_string();
+        assert_eq!(error("  Hello"), "line 1: continuation line without a message");
+        assert_eq!(error("Spen: Hi\nKyle [bot=maybe]: Hi"), "line 2: flag 'bot' expects true or false, found 'maybe'");
+        assert_eq!(error("Spen [shiny]: Hi"), "line 1: unknown flag 'shiny'");
+        assert_eq!(error("> Spen: Hi"), "line 1: reply marker without a following message");
+        assert_eq!(error("Hello there"), "line 1: expected a line in the 'Author: content' format");
+    }
+
+    #[test]
+    fn transcripts_build_messages() {
+        let mut block = DiscordCodeBlock::<ChatParser>::new("discord chat".to_owned(), "Test".to_owned(), Rc::new(Config::default()));
+        block.push_code("Spen: Hello\n  World");
+        let components = ChatParser.parse(&block).unwrap();
+        assert_eq!(components.tree.len(), 1);
+        assert_eq!(components.tree[0].text(), "Hello\nWorld");
+    }
+}
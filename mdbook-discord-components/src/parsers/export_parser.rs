@@ -0,0 +1,559 @@
+use std::{
+    fmt::Display,
+    error::Error,
+    collections::HashMap,
+    ops::Range,
+};
+use serde::Deserialize;
+use anyhow::Result;
+
+use super::{format_date, DiscordCodeBlock, Parser};
+use super::json_parser::{diagnostic, json_field, locate};
+use crate::validation::Problem;
+use crate::components::{
+    message::*,
+    embed::*,
+    *,
+};
+
+static IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp"];
+
+/// Parses [DiscordChatExporter](https://github.com/Tyrrrz/DiscordChatExporter) JSON exports
+///
+/// The code block arguments may contain a message range, like `2..5` or `2..=5`
+pub struct ExportParser;
+
+impl Parser for ExportParser {
+    fn new() -> Self { Self{} }
+
+    fn parse<P: Parser>(&self, code_block: &DiscordCodeBlock<P>) -> Result<Components> {
+        let export = serde_json::from_str::<Export>(&code_block.code).map_err(diagnostic)?;
+        let range = Self::range(code_block, &export.messages)?;
+        let by_id = export.messages.iter()
+            .map(|m| (m.id.as_str(), m))
+            .collect::<HashMap<_, _>>();
+        let mut components = Components::default();
+        for message in &export.messages[range] {
+            let referenced = message.reference.as_ref()
+                .and_then(|r| r.message_id.as_deref())
+                .and_then(|id| by_id.get(id));
+            components.tree.push(message.to_component(referenced.copied()));
+        }
+        Ok(components)
+    }
+
+    fn locate<P: Parser>(&self, code_block: &DiscordCodeBlock<P>, problem: &Problem) -> Option<(Option<String>, usize, usize)> {
+        let export = serde_json::from_str::<Export>(&code_block.code).ok()?;
+        let index = Self::range(code_block, &export.messages).ok()?.start + problem.index;
+        let path = [Some(format!("messages[{index}]")), problem.field.as_deref().map(json_field)].into_iter().flatten().collect::<Vec<_>>().join(".");
+        let (line, column) = locate(&code_block.code, &path);
+        Some((Some(path), line, column))
+    }
+}
+
+impl ExportParser {
+    /// The exported messages selected by the message range of the arguments, all of them without one
+    fn range<P: Parser>(code_block: &DiscordCodeBlock<P>, messages: &[ExportMessage]) -> Result<Range<usize>> {
+        match code_block.arguments.split_whitespace().next() {
+            Some(range) => MessageRange::parse(range)?.resolve(messages),
+            None => Ok(0..messages.len()),
+        }
+    }
+}
+
+/// A range of exported messages, written like a Rust range (`2..5`, `2..=5`, `..5`, `3..` or a single `4`)
+///
+/// Bounds are zero based message indexes, unless they match the ID of an exported message.
+/// Message ID bounds are always inclusive.
+struct MessageRange<'a> {
+    start: Option<&'a str>,
+    end: Option<&'a str>,
+    inclusive: bool,
+}
+
+impl<'a> MessageRange<'a> {
+    fn parse(range: &'a str) -> Result<Self> {
+        let none_if_empty = |s: &'a str| if s.is_empty() { None } else { Some(s) };
+        Ok(if let Some((start, end)) = range.split_once("..=") {
+            Self { start: none_if_empty(start), end: none_if_empty(end), inclusive: true }
+        } else if let Some((start, end)) = range.split_once("..") {
+            Self { start: none_if_empty(start), end: none_if_empty(end), inclusive: false }
+        } else {
+            Self { start: Some(range), end: Some(range), inclusive: true }
+        })
+    }
+
+    fn resolve(&self, messages: &[ExportMessage]) -> Result<Range<usize>> {
+        let start = match self.start {
+            Some(bound) => Self::index(bound, messages)?.0,
+            None => 0,
+        };
+        let end = match self.end {
+            Some(bound) => {
+                let (index, is_id) = Self::index(bound, messages)?;
+                if self.inclusive || is_id { index + 1 } else { index }
+            },
+            None => messages.len(),
+        };
+        if start > end || end > messages.len() {
+            return Err(ExportParserError::new(format!("message range is out of bounds, the export contains {} messages", messages.len())).anyhow());
+        }
+        Ok(start..end)
+    }
+
+    /// Returns the message index of a bound and whether it was given as a message ID
+    fn index(bound: &str, messages: &[ExportMessage]) -> Result<(usize, bool)> {
+        if let Some(index) = messages.iter().position(|m| m.id == bound) {
+            return Ok((index, true));
+        }
+        match bound.parse::<usize>() {
+            Ok(index) => Ok((index, false)),
+            Err(_) => Err(ExportParserError::new(format!("invalid message range bound '{bound}'")).anyhow()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Export {
+    messages: Vec<ExportMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportMessage {
+    id: String,
+    r#type: String,
+    timestamp: Option<String>,
+    timestamp_edited: Option<String>,
+    #[serde(default)]
+    content: String,
+    author: ExportAuthor,
+
+    #[serde(default)]
+    attachments: Vec<ExportAttachment>,
+    #[serde(default)]
+    embeds: Vec<ExportEmbed>,
+    #[serde(default)]
+    reactions: Vec<ExportReaction>,
+    #[serde(default)]
+    mentions: Vec<ExportAuthor>,
+
+    reference: Option<ExportReference>,
+    interaction: Option<ExportInteraction>,
+}
+
+impl ExportMessage {
+    fn to_component(&self, referenced: Option<&ExportMessage>) -> ComponentTree {
+        if let Some(system) = self.system_type() {
+            return self.to_system_component(system);
+        }
+        let message = Message{
+            author: self.author.display_name().to_owned(),
+            avatar: self.author.avatar_url.clone(),
+            bot: self.author.is_bot,
+            edited: self.timestamp_edited.is_some(),
+            role_color: self.author.color.clone(),
+            timestamp: self.timestamp.as_deref().map(format_date),
+            ..Default::default()
+        };
+        let mut tree = if self.content.is_empty() {
+            vec![]
+        } else {
+            vec![ComponentTree::Text(self.content.clone())]
+        };
+        if let Some(interaction) = self.interaction.as_ref() {
+            tree.splice(0..0, [interaction.to_component()]);
+        } else if let Some(referenced) = referenced {
+            let mentions = self.mentions.iter().any(|m| m.id == referenced.author.id);
+            tree.splice(0..0, [referenced.to_reply_component(mentions)]);
+        }
+        for embed in &self.embeds {
+            tree.push(embed.to_component());
+        }
+        if !self.reactions.is_empty() {
+            tree.push(ComponentTree::Node {
+                data: Reactions.into(),
+                nodes: self.reactions.iter().map(|r| r.to_component()).collect(),
+            })
+        }
+        tree.extend(self.attachments.iter().filter_map(|a| a.to_component()));
+        ComponentTree::Node {
+            data: message.into(),
+            nodes: tree,
+        }
+    }
+
+    fn to_reply_component(&self, mentions: bool) -> ComponentTree {
+        let data = Reply{
+            author: self.author.display_name().to_owned(),
+            avatar: self.author.avatar_url.clone(),
+            role_color: self.author.color.clone(),
+            attachment: Some(!self.attachments.is_empty()),
+            edited: Some(self.timestamp_edited.is_some()),
+            bot: Some(self.author.is_bot),
+            verified: None,
+            mentions: Some(mentions),
+            op: None,
+            command: Some(self.interaction.is_some()),
+        };
+        ComponentTree::Node {
+            data: data.into(),
+            nodes: vec![ComponentTree::Text(self.content.clone())],
+        }
+    }
+
+    fn system_type(&self) -> Option<&'static str> {
+        Some(match self.r#type.as_str() {
+            "Call" => "call",
+            "ChannelNameChange" | "ChannelIconChange" => "edit",
+            "ChannelPinnedMessage" => "pin",
+            "GuildMemberJoin" | "RecipientAdd" => "join",
+            "RecipientRemove" => "leave",
+            "GuildBoost" | "GuildBoostTier1" | "GuildBoostTier2" | "GuildBoostTier3" => "boost",
+            "ThreadCreated" => "thread",
+            _ => return None,
+        })
+    }
+
+    fn to_system_component(&self, system: &'static str) -> ComponentTree {
+        let author = self.author.display_name();
+        let content = match self.r#type.as_str() {
+            "Call" => format!("{author} started a call."),
+            "ChannelNameChange" => format!("{author} changed the channel name: **{}**", self.content),
+            "ChannelIconChange" => format!("{author} changed the channel icon."),
+            "ChannelPinnedMessage" => format!("{author} pinned a message to this channel."),
+            "ThreadCreated" => format!("{author} started a thread: **{}**", self.content),
+            _ if !self.content.is_empty() => self.content.clone(),
+            "GuildMemberJoin" | "RecipientAdd" => format!("{author} joined the server."),
+            "RecipientRemove" => format!("{author} left the group."),
+            _ => format!("{author} just boosted the server!"),
+        };
+        let data = SystemMessage{
+            r#type: system.to_owned(),
+            timestamp: self.timestamp.as_deref().map(format_date),
+            channel_name: self.r#type == "ChannelNameChange",
+        };
+        ComponentTree::Node {
+            data: data.into(),
+            nodes: vec![ComponentTree::Text(content)],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportAuthor {
+    id: String,
+    name: String,
+    nickname: Option<String>,
+    color: Option<String>,
+    #[serde(default)]
+    is_bot: bool,
+    avatar_url: Option<String>,
+}
+
+impl ExportAuthor {
+    fn display_name(&self) -> &str {
+        self.nickname.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportReference {
+    message_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportInteraction {
+    name: String,
+    user: ExportAuthor,
+}
+
+impl ExportInteraction {
+    fn to_component(&self) -> ComponentTree {
+        let data = Command{
+            command: format!("/{}", self.name),
+            author: self.user.display_name().to_owned(),
+            avatar: self.user.avatar_url.clone(),
+            role_color: self.user.color.clone(),
+        };
+        ComponentTree::Node {
+            data: data.into(),
+            nodes: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportAttachment {
+    url: String,
+    file_name: String,
+}
+
+impl ExportAttachment {
+    /// Only image attachments can be displayed
+    fn to_component(&self) -> Option<ComponentTree> {
+        let extension = self.file_name.rsplit_once('.')?.1.to_lowercase();
+        if !IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+            return None;
+        }
+        let data = Attachment{
+            url: self.url.clone(),
+            height: None,
+            width: None,
+            alt: Some(self.file_name.clone()),
+        };
+        Some(ComponentTree::Node {
+            data: data.into(),
+            nodes: vec![],
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportEmbed {
+    title: Option<String>,
+    url: Option<String>,
+    timestamp: Option<String>,
+    description: Option<String>,
+    color: Option<String>,
+
+    author: Option<ExportEmbedAuthor>,
+    thumbnail: Option<ExportEmbedImage>,
+    image: Option<ExportEmbedImage>,
+    footer: Option<ExportEmbedFooter>,
+    #[serde(default)]
+    fields: Vec<ExportEmbedField>,
+}
+
+impl ExportEmbed {
+    fn to_component(&self) -> ComponentTree {
+        let mut embed = Embed{
+            embed_title: self.title.clone(),
+            url: self.url.clone(),
+            color: self.color.clone(),
+            image: self.image.as_ref().map(|i| i.url.clone()),
+            thumbnail: self.thumbnail.as_ref().map(|t| t.url.clone()),
+            ..Default::default()
+        };
+        if let Some(author) = self.author.as_ref() {
+            embed.author_name = Some(author.name.clone());
+            embed.author_url = author.url.clone();
+            embed.author_image = author.icon_url.clone();
+        }
+        let mut tree = Vec::<ComponentTree>::new();
+        if let Some(description) = self.description.as_ref() {
+            tree.push(ComponentTree::Node {
+                data: EmbedDescription.into(),
+                nodes: vec![ComponentTree::Text(description.clone())],
+            })
+        }
+        if !self.fields.is_empty() {
+            let mut inline_index = 0;
+            tree.push(ComponentTree::Node {
+                data: EmbedFields.into(),
+                nodes: self.fields.iter().map(|f| {
+                    // Discord places up to three inline fields in a row
+                    inline_index = if f.is_inline { inline_index % 3 + 1 } else { 0 };
+                    f.to_component(inline_index)
+                }).collect(),
+            })
+        }
+        if self.footer.is_some() || self.timestamp.is_some() {
+            let text = self.footer.as_ref().and_then(|f| f.text.clone());
+            tree.push(ComponentTree::Node {
+                data: EmbedFooter{
+                    footer_image: self.footer.as_ref().and_then(|f| f.icon_url.clone()),
+                    timestamp: self.timestamp.as_deref().map(format_date),
+                }.into(),
+                nodes: text.map(|t| vec![ComponentTree::Text(t)]).unwrap_or_default(),
+            })
+        }
+        ComponentTree::Node {
+            data: embed.into(),
+            nodes: tree,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportEmbedAuthor {
+    name: String,
+    url: Option<String>,
+    icon_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportEmbedImage {
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportEmbedFooter {
+    text: Option<String>,
+    icon_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportEmbedField {
+    name: String,
+    value: String,
+    #[serde(default)]
+    is_inline: bool,
+}
+
+impl ExportEmbedField {
+    fn to_component(&self, inline_index: usize) -> ComponentTree {
+        let data = EmbedField{
+            field_title: self.name.clone(),
+            inline: self.is_inline,
+            inline_index: if self.is_inline { Some(inline_index) } else { None },
+        };
+        ComponentTree::Node {
+            data: data.into(),
+            nodes: vec![ComponentTree::Text(self.value.clone())],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportEmoji {
+    id: Option<String>,
+    name: String,
+    code: Option<String>,
+    image_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportReaction {
+    emoji: ExportEmoji,
+    #[serde(default)]
+    count: usize,
+}
+
+impl ExportReaction {
+    fn to_component(&self) -> ComponentTree {
+        // Standard emoji are exported with an empty ID
+        let id = self.emoji.id.as_ref().filter(|id| !id.is_empty());
+        let name = match (id, self.emoji.code.as_ref()) {
+            (None, _) => self.emoji.name.clone(),
+            (Some(_), Some(code)) => format!(":{code}:"),
+            (Some(_), None) => format!(":{}:", self.emoji.name),
+        };
+        let data = Reaction{
+            name,
+            emoji: self.emoji.image_url.clone(),
+            count: self.count,
+            ..Default::default()
+        };
+        ComponentTree::Node {
+            data: data.into(),
+            nodes: vec![],
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ExportParserError {
+    message: String,
+}
+
+impl ExportParserError {
+    fn new<S: Into<String>>(message: S) -> Self {
+        Self { message: message.into() }
+    }
+
+    fn anyhow(self) -> anyhow::Error {
+        anyhow::Error::new(self)
+    }
+}
+
+impl Display for ExportParserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ExportParserError {}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use crate::config::Config;
+    use crate::validation::Severity;
+    use super::*;
+
+    static EXPORT: &str = r#"{"messages": [
+        {"id": "100", "type": "Default", "content": "First", "author": {"id": "1", "name": "spen", "nickname": "Spen"}},
+        {"id": "101", "type": "Reply", "content": "Second", "author": {"id": "2", "name": "kyle", "isBot": true},
+            "reference": {"messageId": "100"}, "mentions": [{"id": "1", "name": "spen"}],
+            "attachments": [{"url": "cat.png", "fileName": "cat.png"}, {"url": "notes.txt", "fileName": "notes.txt"}],
+            "reactions": [{"emoji": {"id": "", "name": "👍"}, "count": 2}, {"emoji": {"id": "5", "name": "blob", "imageUrl": "blob.png"}}]},
+        {"id": "102", "type": "GuildMemberJoin", "content": "", "author": {"id": "3", "name": "snazzah"}},
+        {"id": "103", "type": "Default", "content": "Last", "author": {"id": "1", "name": "spen", "nickname": "Spen"}}
+    ]}"#;
+
+    fn block(arguments: &str) -> DiscordCodeBlock<ExportParser> {
+        let mut block = DiscordCodeBlock::<ExportParser>::new("discord export".to_owned(), "Test".to_owned(), Rc::new(Config::default()));
+        block.set_arguments(arguments);
+        block.push_code(EXPORT);
+        block
+    }
+
+    fn parse(arguments: &str) -> Result<Components> {
+        ExportParser.parse(&block(arguments))
+    }
+
+    fn texts(components: &Components) -> Vec<String> {
+        components.tree.iter().map(ComponentTree::text).collect()
+    }
+
+    #[test]
+    fn ranges_select_messages_by_index_or_id() {
+        assert_eq!(texts(&parse("").unwrap()).len(), 4);
+        assert_eq!(texts(&parse("1..3").unwrap()), ["Second", "snazzah joined the server."]);
+        assert_eq!(texts(&parse("1..=3").unwrap()), ["Second", "snazzah joined the server.", "Last"]);
+        assert_eq!(texts(&parse("..1").unwrap()), ["First"]);
+        assert_eq!(texts(&parse("3").unwrap()), ["Last"]);
+        assert_eq!(texts(&parse("101..102").unwrap()), ["Second", "snazzah joined the server."]);
+    }
+
+    #[test]
+    fn problems_are_located_in_the_selected_range() {
+        let problem = Problem { severity: Severity::Error, index: 1, field: Some("content".to_owned()), message: String::new() };
+        let (field, line, column) = ExportParser.locate(&block("1..3"), &problem).unwrap();
+        assert_eq!(field.as_deref(), Some("messages[2].content"));
+        assert_eq!((line, column), (7, 50));
+    }
+
+    #[test]
+    fn invalid_ranges_are_errors() {
+        let error = |arguments| parse(arguments).err().unwrap().to_string();
+        assert_eq!(error("2..9"), "message range is out of bounds, the export contains 4 messages");
+        assert_eq!(error("first..2"), "invalid message range bound 'first'");
+    }
+
+    #[test]
+    fn replies_reactions_and_images_are_converted() {
+        let components = parse("1").unwrap();
+        let message = &components.tree[0];
+        assert!(message.data::<Message>().unwrap().bot);
+        let (reply, node) = message.children::<Reply>().next().unwrap();
+        assert_eq!((reply.author.as_str(), reply.mentions), ("Spen", Some(true)));
+        assert_eq!(node.text(), "First");
+        let reactions = message.children::<Reactions>()
+            .flat_map(|(_, node)| node.children::<Reaction>())
+            .map(|(reaction, _)| (reaction.name.as_str(), reaction.count))
+            .collect::<Vec<_>>();
+        assert_eq!(reactions, [("👍", 2), (":blob:", 0)]);
+        let attachments = message.children::<Attachment>().map(|(attachment, _)| attachment.url.as_str()).collect::<Vec<_>>();
+        assert_eq!(attachments, ["cat.png"]);
+    }
+}
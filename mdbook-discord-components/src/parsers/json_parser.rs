@@ -0,0 +1,683 @@
+use std::collections::HashMap;
+use serde_aux::field_attributes::deserialize_option_number_from_string;
+use serde::Deserialize;
+use regex::{Regex, Captures};
+use anyhow::Result;
+
+use super::{format_date, Diagnostic, DiscordCodeBlock, Parser};
+use super::yaml_parser::FIELD_SEGMENT_REGEX;
+use crate::discord::User;
+use crate::validation::Problem;
+use crate::config::Config;
+use crate::emoji;
+use crate::components::{
+    components::*,
+    message::*,
+    embed::*,
+    *,
+};
+
+lazy_static::lazy_static! {
+    static ref USER_MENTION_REGEX: Regex = Regex::new("<@!?([0-9]+)>").unwrap();
+}
+
+static EPHEMERAL_FLAG: u64 = 1 << 6;
+
+/// Parses raw Discord API message objects, as returned by the REST API or gateway
+pub struct JsonParser;
+
+impl Parser for JsonParser {
+    fn new() -> Self { Self{} }
+
+    fn parse<P: Parser>(&self, code_block: &DiscordCodeBlock<P>) -> Result<Components> {
+        let mut components = Components::default();
+        // A single message may be written without the surrounding array
+        let messages = if code_block.code.trim_start().starts_with('[') {
+            serde_json::from_str::<Vec<JsonMessage>>(&code_block.code).map_err(diagnostic)?
+        } else {
+            vec![serde_json::from_str::<JsonMessage>(&code_block.code).map_err(diagnostic)?]
+        };
+        for message in messages {
+            components.tree.push(message.into_component(&code_block.config));
+        }
+        Ok(components)
+    }
+
+    fn locate<P: Parser>(&self, code_block: &DiscordCodeBlock<P>, problem: &Problem) -> Option<(Option<String>, usize, usize)> {
+        let code = &code_block.code;
+        let index = code.trim_start().starts_with('[').then(|| format!("[{}]", problem.index));
+        let path = [index, problem.field.as_deref().map(json_field)].into_iter().flatten().collect::<Vec<_>>().join(".");
+        let (line, column) = locate(code, &path);
+        Some((Some(path).filter(|path| !path.is_empty()), line, column))
+    }
+}
+
+/// A JSON error at the line and column serde_json reports it at
+pub(super) fn diagnostic(err: serde_json::Error) -> anyhow::Error {
+    let message = err.to_string();
+    let position = format!(" at line {} column {}", err.line(), err.column());
+    Diagnostic::new(message.strip_suffix(&position).unwrap_or(&message), None, err.line(), err.column().max(1)).anyhow()
+}
+
+/// The path of a field of the validator in a Discord API message, where buttons are nested in their action row
+pub(super) fn json_field(field: &str) -> String {
+    if field == "command" {
+        return "interaction".to_owned();
+    }
+    match field.strip_prefix("components[").and_then(|rest| rest.split_once("][")) {
+        Some((row, rest)) => format!("components[{row}].components[{rest}"),
+        None => field.to_owned(),
+    }
+}
+
+/// One based line and column of the field at `path` in JSON code, or of the deepest part of the path found
+pub(super) fn locate(code: &str, path: &str) -> (usize, usize) {
+    let mut scanner = JsonScanner { code, pos: 0 };
+    let offset = scanner.find(&FIELD_SEGMENT_REGEX.captures_iter(path).map(|segment| segment[0].to_owned()).collect::<Vec<_>>());
+    let line_start = code[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (code[..offset].matches('\n').count() + 1, code[line_start..offset].chars().count() + 1)
+}
+
+/// Walks valid JSON by hand, as serde_json doesn't keep the positions of values
+struct JsonScanner<'a> {
+    code: &'a str,
+    pos: usize,
+}
+
+impl<'a> JsonScanner<'a> {
+    /// Offset of the value at the path segments, like `[2]` and `embeds`, from the value at the current position.
+    /// Object members are found at their key
+    fn find(&mut self, path: &[String]) -> usize {
+        self.skip_whitespace();
+        let start = self.pos;
+        let Some((segment, rest)) = path.split_first() else {
+            return start;
+        };
+        let index = segment.strip_prefix('[').and_then(|i| i.strip_suffix(']')).and_then(|i| i.parse::<usize>().ok());
+        let open = if index.is_some() { '[' } else { '{' };
+        if self.peek() != Some(open) {
+            return start;
+        }
+        self.pos += 1;
+        let mut i = 0;
+        loop {
+            self.skip_whitespace();
+            if matches!(self.peek(), Some(']' | '}') | None) {
+                return start;
+            }
+            let member = self.pos;
+            let found = match index {
+                Some(index) => index == i,
+                None => {
+                    let key = self.string();
+                    self.skip_whitespace();
+                    self.pos += 1;
+                    key == segment
+                },
+            };
+            if found {
+                return if rest.is_empty() { member } else { self.find(rest) };
+            }
+            self.skip_value();
+            self.skip_whitespace();
+            if self.peek() == Some(',') {
+                self.pos += 1;
+            }
+            i += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.code[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.pos = self.code.len() - self.code[self.pos..].trim_start().len();
+    }
+
+    /// The raw content of the string at the current position, escapes are left as they are
+    fn string(&mut self) -> &'a str {
+        let start = self.pos + 1;
+        let mut escaped = false;
+        for (i, c) in self.code[start..].char_indices() {
+            match c {
+                '"' if !escaped => {
+                    self.pos = start + i + 1;
+                    return &self.code[start..start + i];
+                },
+                '\\' => escaped = !escaped,
+                _ => escaped = false,
+            }
+        }
+        self.pos = self.code.len();
+        &self.code[start..]
+    }
+
+    fn skip_value(&mut self) {
+        let mut depth = 0usize;
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None => return,
+                Some('"') => {
+                    self.string();
+                },
+                Some('[' | '{') => {
+                    depth += 1;
+                    self.pos += 1;
+                },
+                Some(']' | '}') => {
+                    depth = depth.saturating_sub(1);
+                    self.pos += 1;
+                },
+                Some(',' | ':') if depth > 0 => self.pos += 1,
+                Some(_) => {
+                    let len = self.code[self.pos..].find(|c: char| ",:[]{}\"".contains(c) || c.is_whitespace()).unwrap_or(self.code.len() - self.pos);
+                    self.pos += len.max(1);
+                },
+            }
+            if depth == 0 {
+                return;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonMessage {
+    #[serde(default)]
+    r#type: u8,
+    author: User,
+    #[serde(default)]
+    content: String,
+    timestamp: Option<String>,
+    edited_timestamp: Option<String>,
+    #[serde(default)]
+    flags: u64,
+
+    #[serde(default)]
+    mentions: Vec<User>,
+    #[serde(default)]
+    embeds: Vec<JsonEmbed>,
+    #[serde(default)]
+    reactions: Vec<JsonReaction>,
+    #[serde(default)]
+    attachments: Vec<JsonAttachment>,
+    #[serde(default)]
+    components: Vec<JsonComponent>,
+
+    referenced_message: Option<Box<JsonMessage>>,
+    interaction: Option<JsonInteraction>,
+}
+
+impl JsonMessage {
+    fn into_component(self, config: &Config) -> ComponentTree {
+        if let Some(system) = self.system_type() {
+            return self.into_system_component(system);
+        }
+        let message = Message{
+            author: self.author.display_name(),
+            avatar: Some(self.author.avatar_url()),
+            bot: self.author.is_bot(),
+            verified: self.author.is_verified_bot(),
+            edited: self.edited_timestamp.is_some(),
+            ephemeral: self.flags & EPHEMERAL_FLAG != 0,
+            timestamp: self.timestamp.as_deref().map(format_date),
+            ..Default::default()
+        };
+
+        let content = resolve_user_mentions(&self.content, &self.mentions);
+        let mut tree = if content.is_empty() {
+            vec![]
+        } else {
+            vec![ComponentTree::Text(content)]
+        };
+        if let Some(interaction) = self.interaction {
+            tree.splice(0..0, [interaction.into_component()]);
+        } else if let Some(referenced) = self.referenced_message {
+            let mentions = self.mentions.iter().any(|u| u.id() == referenced.author.id());
+            tree.splice(0..0, [referenced.into_reply_component(mentions)]);
+        }
+        for embed in self.embeds {
+            tree.push(embed.into_component());
+        }
+        if !self.reactions.is_empty() {
+            tree.push(ComponentTree::Node {
+                data: Reactions.into(),
+                nodes: self.reactions.into_iter().map(|r| r.into_component(config)).collect(),
+            })
+        }
+        tree.extend(self.attachments.into_iter().filter_map(|a| a.into_component()));
+        let rows = self.components.into_iter().filter_map(|c| c.into_component(config)).collect::<Vec<_>>();
+        if !rows.is_empty() {
+            tree.push(ComponentTree::Node {
+                data: Attachments.into(),
+                nodes: rows,
+            });
+        }
+        ComponentTree::Node {
+            data: message.into(),
+            nodes: tree,
+        }
+    }
+
+    fn into_reply_component(self, mentions: bool) -> ComponentTree {
+        let data = Reply{
+            author: self.author.display_name(),
+            avatar: Some(self.author.avatar_url()),
+            role_color: None,
+            attachment: Some(!self.attachments.is_empty()),
+            edited: Some(self.edited_timestamp.is_some()),
+            bot: Some(self.author.is_bot()),
+            verified: Some(self.author.is_verified_bot()),
+            mentions: Some(mentions),
+            op: None,
+            command: Some(self.interaction.is_some()),
+        };
+        ComponentTree::Node {
+            data: data.into(),
+            nodes: vec![ComponentTree::Text(resolve_user_mentions(&self.content, &self.mentions))],
+        }
+    }
+
+    /// Maps Discord's system message types onto discord-components system message types
+    fn system_type(&self) -> Option<&'static str> {
+        Some(match self.r#type {
+            3 => "call",
+            4 | 5 => "edit",
+            6 => "pin",
+            7 => "join",
+            8..=11 => "boost",
+            18 => "thread",
+            _ => return None,
+        })
+    }
+
+    fn into_system_component(self, system: &'static str) -> ComponentTree {
+        let author = self.author.display_name();
+        let content = match self.r#type {
+            3 => format!("{author} started a call."),
+            4 => format!("{author} changed the channel name: **{}**", self.content),
+            5 => format!("{author} changed the channel icon."),
+            6 => format!("{author} pinned a message to this channel."),
+            7 => format!("{author} joined the server."),
+            18 => format!("{author} started a thread: **{}**", self.content),
+            _ => format!("{author} just boosted the server!"),
+        };
+        let data = SystemMessage{
+            r#type: system.to_owned(),
+            timestamp: self.timestamp.as_deref().map(format_date),
+            channel_name: self.r#type == 4,
+        };
+        ComponentTree::Node {
+            data: data.into(),
+            nodes: vec![ComponentTree::Text(content)],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonInteraction {
+    name: String,
+    user: User,
+}
+
+impl JsonInteraction {
+    fn into_component(self) -> ComponentTree {
+        let data = Command{
+            command: format!("/{}", self.name),
+            author: self.user.display_name(),
+            avatar: Some(self.user.avatar_url()),
+            role_color: None,
+        };
+        ComponentTree::Node {
+            data: data.into(),
+            nodes: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonEmbed {
+    title: Option<String>,
+    url: Option<String>,
+    color: Option<u32>,
+    description: Option<String>,
+    timestamp: Option<String>,
+
+    image: Option<JsonEmbedMedia>,
+    thumbnail: Option<JsonEmbedMedia>,
+    author: Option<JsonEmbedAuthor>,
+    footer: Option<JsonEmbedFooter>,
+    #[serde(default)]
+    fields: Vec<JsonEmbedField>,
+}
+
+impl JsonEmbed {
+    fn into_component(self) -> ComponentTree {
+        let mut embed = Embed{
+            embed_title: self.title,
+            url: self.url,
+            color: self.color.map(|c| format!("#{c:06x}")),
+            image: self.image.map(|i| i.url),
+            thumbnail: self.thumbnail.map(|t| t.url),
+            ..Default::default()
+        };
+        if let Some(author) = self.author {
+            embed.author_name = Some(author.name);
+            embed.author_url = author.url;
+            embed.author_image = author.icon_url;
+        }
+        let mut tree = Vec::<ComponentTree>::new();
+        if let Some(description) = self.description {
+            tree.push(ComponentTree::Node {
+                data: EmbedDescription.into(),
+                nodes: vec![ComponentTree::Text(description)],
+            })
+        }
+        if !self.fields.is_empty() {
+            let mut inline_index = 0;
+            tree.push(ComponentTree::Node {
+                data: EmbedFields.into(),
+                nodes: self.fields.into_iter().map(|f| {
+                    // Discord places up to three inline fields in a row
+                    inline_index = if f.inline { inline_index % 3 + 1 } else { 0 };
+                    f.into_component(inline_index)
+                }).collect(),
+            })
+        }
+        if self.footer.is_some() || self.timestamp.is_some() {
+            let (text, image) = self.footer.map(|f| (f.text, f.icon_url)).unwrap_or_default();
+            tree.push(ComponentTree::Node {
+                data: EmbedFooter{
+                    footer_image: image,
+                    timestamp: self.timestamp.as_deref().map(format_date),
+                }.into(),
+                nodes: text.map(|t| vec![ComponentTree::Text(t)]).unwrap_or_default(),
+            })
+        }
+        ComponentTree::Node {
+            data: embed.into(),
+            nodes: tree,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonEmbedMedia {
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonEmbedAuthor {
+    name: String,
+    url: Option<String>,
+    icon_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonEmbedFooter {
+    text: Option<String>,
+    icon_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonEmbedField {
+    name: String,
+    value: String,
+    #[serde(default)]
+    inline: bool,
+}
+
+impl JsonEmbedField {
+    fn into_component(self, inline_index: usize) -> ComponentTree {
+        let data = EmbedField{
+            field_title: self.name,
+            inline: self.inline,
+            inline_index: if self.inline { Some(inline_index) } else { None },
+        };
+        ComponentTree::Node {
+            data: data.into(),
+            nodes: vec![ComponentTree::Text(self.value)],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonEmoji {
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    id: Option<u64>,
+    name: Option<String>,
+    #[serde(default)]
+    animated: bool,
+}
+
+impl JsonEmoji {
+    /// Custom emoji are served by the Discord CDN, standard ones by Twemoji
+    fn url(&self, config: &Config) -> Option<String> {
+        match self.id {
+            Some(id) => Some(emoji::custom_url(id, self.animated)),
+            None => emoji::resolve(self.name.as_deref()?, &config.emoji_base_url).map(|(url, _)| url),
+        }
+    }
+
+    fn name(&self, config: &Config) -> Option<String> {
+        let name = self.name.clone()?;
+        if self.id.is_some() {
+            return Some(format!(":{name}:"));
+        }
+        Some(emoji::resolve(&name, &config.emoji_base_url).map(|(_, shortcode)| shortcode).unwrap_or(name))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonReaction {
+    #[serde(default)]
+    count: usize,
+    #[serde(default)]
+    me: bool,
+    emoji: JsonEmoji,
+}
+
+impl JsonReaction {
+    fn into_component(self, config: &Config) -> ComponentTree {
+        let mut data = Reaction{
+            emoji: self.emoji.url(config),
+            count: self.count,
+            interactive: true,
+            reacted: self.me,
+            ..Default::default()
+        };
+        if let Some(name) = self.emoji.name(config) {
+            data.name = name;
+        }
+        ComponentTree::Node {
+            data: data.into(),
+            nodes: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonAttachment {
+    url: String,
+    filename: Option<String>,
+    description: Option<String>,
+    content_type: Option<String>,
+    height: Option<u64>,
+    width: Option<u64>,
+}
+
+impl JsonAttachment {
+    /// Only image attachments can be displayed
+    fn into_component(self) -> Option<ComponentTree> {
+        let is_image = self.content_type.as_deref().map(|t| t.starts_with("image/")).unwrap_or(self.width.is_some());
+        if !is_image {
+            return None;
+        }
+        let data = Attachment{
+            url: self.url,
+            height: self.height,
+            width: self.width,
+            alt: self.description.or(self.filename),
+        };
+        Some(ComponentTree::Node {
+            data: data.into(),
+            nodes: vec![],
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct JsonComponent {
+    r#type: u8,
+    style: Option<u8>,
+    label: Option<String>,
+    emoji: Option<JsonEmoji>,
+    url: Option<String>,
+    #[serde(default)]
+    disabled: bool,
+    #[serde(default)]
+    components: Vec<JsonComponent>,
+}
+
+impl JsonComponent {
+    /// Converts action rows and buttons, other component types have no discord-components equivalent
+    fn into_component(self, config: &Config) -> Option<ComponentTree> {
+        match self.r#type {
+            1 => {
+                let buttons = self.components.into_iter().filter_map(|c| c.into_component(config)).collect::<Vec<_>>();
+                if buttons.is_empty() {
+                    return None;
+                }
+                Some(ComponentTree::Node {
+                    data: ActionRow.into(),
+                    nodes: buttons,
+                })
+            },
+            2 => {
+                let r#type = match self.style {
+                    Some(1) => "primary",
+                    Some(3) => "success",
+                    Some(4) => "destructive",
+                    _ => "secondary",
+                };
+                let data = Button{
+                    r#type: r#type.to_owned(),
+                    disabled: self.disabled,
+                    emoji: self.emoji.as_ref().and_then(|e| e.url(config)),
+                    emoji_name: self.emoji.as_ref().and_then(|e| e.name(config)),
+                    url: self.url,
+                };
+                Some(ComponentTree::Node {
+                    data: data.into(),
+                    nodes: vec![ComponentTree::Text(self.label.unwrap_or_default())],
+                })
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Replaces `<@id>` mentions of users included in the message with their display names
+fn resolve_user_mentions(content: &str, mentions: &[User]) -> String {
+    let names = mentions.iter().map(|u| (u.id(), u.display_name())).collect::<HashMap<_, _>>();
+    USER_MENTION_REGEX.replace_all(content, |captures: &Captures| {
+        match captures[1].parse::<u64>().ok().and_then(|id| names.get(&id)) {
+            Some(name) => format!("<@{name}>"),
+            None => captures[0].to_owned(),
+        }
+    }).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use super::*;
+
+    fn parse(code: &str) -> Result<Components> {
+        let mut block = DiscordCodeBlock::<JsonParser>::new("discord json".to_owned(), "Test".to_owned(), Rc::new(Config::default()));
+        block.push_code(code);
+        JsonParser.parse(&block)
+    }
+
+    static AUTHOR: &str = r#""author": {"id": "1", "username": "spen", "global_name": "Spen", "avatar": null}"#;
+
+    #[test]
+    fn single_messages_and_arrays_are_parsed() {
+        let single = parse(&format!(r#"{{{AUTHOR}, "content": "Hello"}}"#)).unwrap();
+        assert_eq!(single.tree.len(), 1);
+        assert_eq!(single.tree[0].data::<Message>().unwrap().author, "Spen");
+        assert_eq!(single.tree[0].text(), "Hello");
+
+        let array = parse(&format!(r#"  [{{{AUTHOR}, "content": "Hello"}}, {{{AUTHOR}, "content": "Bye"}}]"#)).unwrap();
+        assert_eq!(array.tree.iter().map(ComponentTree::text).collect::<Vec<_>>(), ["Hello", "Bye"]);
+    }
+
+    #[test]
+    fn errors_in_arrays_point_at_the_element() {
+        let code = format!("[\n  {{{AUTHOR}, \"content\": \"Hello\"}},\n  {{{AUTHOR}, \"embeds\": [{{\"color\": \"red\"}}]}}\n]");
+        let err = parse(&code).err().unwrap();
+        assert_eq!(err.to_string(), "invalid type: string \"red\", expected u32");
+        assert_eq!(err.downcast_ref::<Diagnostic>().unwrap().line, 3);
+    }
+
+    #[test]
+    fn fields_are_located_in_the_code() {
+        let code = format!("[\n  {{{AUTHOR}, \"content\": \"Hi\"}},\n  {{{AUTHOR},\n    \"content\": \"[\\\"x\\\"], {{\",\n    \"components\": [{{\"type\": 1, \"components\": [\n      {{\"type\": 2, \"label\": \"Yes\"}}]}}]\n  }}\n]");
+        assert_eq!(locate(&code, "[1].content"), (4, 5));
+        assert_eq!(locate(&code, &format!("[1].{}", json_field("components[0][0].label"))), (6, 19));
+        // Missing parts point at the deepest part found
+        assert_eq!(locate(&code, "[1].embeds[0]"), (3, 3));
+    }
+
+    #[test]
+    fn mentions_replies_and_embeds_are_converted() {
+        let code = format!(r#"{{
+            {AUTHOR},
+            "content": "Hi <@2>",
+            "mentions": [{{"id": "2", "username": "kyle", "avatar": null}}],
+            "referenced_message": {{"author": {{"id": "2", "username": "kyle", "avatar": null}}, "content": "Hey"}},
+            "embeds": [{{"title": "Embed", "color": 16711680, "fields": [
+                {{"name": "A", "value": "1", "inline": true}},
+                {{"name": "B", "value": "2", "inline": true}},
+                {{"name": "C", "value": "3"}}
+            ]}}]
+        }}"#);
+        let components = parse(&code).unwrap();
+        let message = &components.tree[0];
+        assert_eq!(message.text(), "Hi <@kyle>");
+        let (reply, _) = message.children::<Reply>().next().unwrap();
+        assert_eq!(reply.author, "kyle");
+        assert_eq!(reply.mentions, Some(true));
+        let (embed, node) = message.children::<Embed>().next().unwrap();
+        assert_eq!(embed.color.as_deref(), Some("#ff0000"));
+        let fields = node.children::<EmbedFields>().flat_map(|(_, node)| node.children::<EmbedField>()).map(|(field, _)| field.inline_index).collect::<Vec<_>>();
+        assert_eq!(fields, [Some(1), Some(2), None]);
+    }
+
+    #[test]
+    fn system_messages_and_components_are_converted() {
+        let components = parse(&format!(r#"[
+            {{{AUTHOR}, "type": 7}},
+            {{{AUTHOR}, "type": 4, "content": "general"}},
+            {{{AUTHOR}, "content": "Pick", "flags": 64, "components": [
+                {{"type": 1, "components": [{{"type": 2, "style": 3, "label": "Yes"}}, {{"type": 3}}]}}
+            ]}}
+        ]"#)).unwrap();
+        let system = components.tree[0].data::<SystemMessage>().unwrap();
+        assert_eq!(system.r#type, "join");
+        assert_eq!(components.tree[0].text(), "Spen joined the server.");
+
+        assert_eq!(components.tree[1].text(), "Spen changed the channel name: **general**");
+
+        let message = &components.tree[2];
+        assert!(message.data::<Message>().unwrap().ephemeral);
+        let buttons = message.children::<Attachments>()
+            .flat_map(|(_, node)| node.children::<ActionRow>())
+            .flat_map(|(_, node)| node.children::<Button>())
+            .map(|(button, node)| (button.r#type.as_str(), node.text()))
+            .collect::<Vec<_>>();
+        assert_eq!(buttons, [("success", "Yes".to_owned())]);
+    }
+}
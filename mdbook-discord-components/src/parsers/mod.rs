@@ -1,41 +1,131 @@
-use std::fmt::Display;
-use anyhow::{Result, Error};
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    path::PathBuf,
+    rc::Rc,
+};
+use anyhow::{anyhow, Result, Error};
 use pulldown_cmark::Event;
+use regex::Regex;
 
 mod yaml_parser;
+mod json_parser;
+mod chat_parser;
+mod export_parser;
 
 pub use yaml_parser::YamlParser;
+pub use json_parser::JsonParser;
+pub use chat_parser::ChatParser;
+pub use export_parser::ExportParser;
 
 #[cfg(feature = "http")]
 use crate::discord::Discord;
 use crate::{
-    components::Components,
+    components::{Components, ComponentTree},
     generators::Generator,
+    config::{Config, DisplayOptions},
+    validation::{Problem, Severity},
 };
 
-pub static DISCORD_COMPONENTS_SCRIPT: &str = "<script type=\"module\" src=\"https://unpkg.com/@skyra/discord-components-core\"></script>\n";
+/// Pinned release of the discord-components script, the default `script-src`
+pub static DISCORD_COMPONENTS_SCRIPT_URL: &str = "https://unpkg.com/@skyra/discord-components-core@3.6.1";
 
 #[cfg(feature = "http")]
 lazy_static::lazy_static! {
     static ref DISCORD_CLIENT: Discord = Discord::default();
 }
 
+lazy_static::lazy_static! {
+    static ref USER_ID_MENTION_REGEX: Regex = Regex::new("<!?@!?([0-9]+)>").unwrap();
+}
+
+pub static PARSER_NAMES: &[&str] = &["yaml", "json", "chat", "export"];
+
 pub trait Parser: Sized {
     fn new() -> Self;
     fn parse<P: Parser>(&self, code_block: &DiscordCodeBlock<P>) -> Result<Components>;
+
+    /// Where the field of a problem the validator found is in the code, as the path of the field written in the code
+    /// and a one based line and column. Problems are reported by message number when `None`
+    fn locate<P: Parser>(&self, _code_block: &DiscordCodeBlock<P>, _problem: &Problem) -> Option<(Option<String>, usize, usize)> {
+        None
+    }
+}
+
+/// Parser-erased code block, used where the parser is only known at runtime
+pub trait CodeBlock<G: Generator> {
+    fn block_name(&self) -> &str;
+    fn code(&self) -> &str;
+    fn push_code(&mut self, code: &str);
+    fn set_arguments(&mut self, arguments: &str);
+    fn set_source_file(&mut self, path: PathBuf);
+    fn set_position(&mut self, line: usize, column: usize);
+    fn set_path_to_root(&mut self, path: &str);
+    fn skip_head(&mut self);
+    fn build<'a>(&self) -> Result<Vec<Event<'a>>>;
+}
+
+/// Creates a code block for one of the [`PARSER_NAMES`], or `None` if the parser is unknown
+pub fn new_code_block<G: Generator + 'static>(parser_name: &str, block_name: String, chapter_name: String, config: Rc<Config>) -> Option<Box<dyn CodeBlock<G>>> {
+    Some(match parser_name {
+        "yaml" => Box::new(DiscordCodeBlock::<YamlParser>::new(block_name, chapter_name, config)),
+        "json" => Box::new(DiscordCodeBlock::<JsonParser>::new(block_name, chapter_name, config)),
+        "chat" => Box::new(DiscordCodeBlock::<ChatParser>::new(block_name, chapter_name, config)),
+        "export" => Box::new(DiscordCodeBlock::<ExportParser>::new(block_name, chapter_name, config)),
+        _ => return None,
+    })
 }
 
 pub struct DiscordCodeBlock<P: Parser> {
     pub block_name: String,
     chapter_name: String,
-    embed_script: bool,
+    source_file: Option<PathBuf>,
+    positions: Vec<CodePosition>,
+    path_to_root: String,
+    include_head: bool,
+    display: DisplayOptions,
+    config: Rc<Config>,
     code: String,
+    arguments: String,
     parser: P,
 }
 
 impl<P: Parser> DiscordCodeBlock<P> {
-    pub fn new(block_name: String, chapter_name: String, embed_script: bool) -> Self {
-        Self { block_name, chapter_name, embed_script, code: String::new(), parser: P::new() }
+    pub fn new(block_name: String, chapter_name: String, config: Rc<Config>) -> Self {
+        Self { block_name, chapter_name, source_file: None, positions: Vec::new(), path_to_root: String::new(), include_head: true, display: config.display(), config, code: String::new(), arguments: String::new(), parser: P::new() }
+    }
+
+    /// Sets the file the code was read from, the chapter's file or a file included into it
+    pub fn set_source_file(&mut self, path: PathBuf) {
+        self.source_file = Some(path);
+    }
+
+    /// Sets where the code pushed next starts in the source file, with one based line and column.
+    /// Errors are reported at these positions, instead of the line in the code block
+    pub fn set_position(&mut self, line: usize, column: usize) {
+        let code_line = self.code.matches('\n').count();
+        self.positions.retain(|position| position.code_line != code_line);
+        self.positions.push(CodePosition{ code_line, line, column });
+    }
+
+    /// Sets where the book root is relative to the chapter, like `../`, which relative paths in the head start from
+    pub fn set_path_to_root<S: Into<String>>(&mut self, path: S) {
+        self.path_to_root = path.into();
+    }
+
+    /// Leaves out the head of the generated output, like the script tag, when the chapter already includes it
+    pub fn skip_head(&mut self) {
+        self.include_head = false;
+    }
+
+    /// Sets the arguments, like the words following the parser name in a code block info string.
+    /// Display options, like `theme=light` or `compact`, are taken out and the rest is left to the parser
+    pub fn set_arguments<S: Into<String>>(&mut self, arguments: S) {
+        let arguments = arguments.into();
+        self.arguments = arguments.split_whitespace()
+            .filter(|word| !self.display.apply(word))
+            .collect::<Vec<_>>()
+            .join(" ");
     }
 
     pub fn push_code<S: Into<String>>(&mut self, code: S) {
@@ -43,38 +133,252 @@ impl<P: Parser> DiscordCodeBlock<P> {
     }
 
     pub fn build<'a, G: Generator>(&self) -> Result<Vec<Event<'a>>> {
-        match self.parser.parse(self) {
-            Ok(result) => {
-                let res = G::new().generate(result)?;
-                Ok(if self.embed_script {
-                    vec![
-                        Event::Html((DISCORD_COMPONENTS_SCRIPT.to_owned()).into()),
-                        res,
-                    ]
-                } else {
-                    vec![res]
-                })
+        match self.parser.parse(self).and_then(|components| self.validate(components)) {
+            Ok(mut result) => {
+                resolve_user_mentions(&mut result, &self.config);
+                result.display = self.display;
+                let generator = G::new(self.config.clone());
+                let res = generator.generate(result)?;
+                let head = if self.include_head { generator.head(&self.path_to_root) } else { None };
+                Ok(head.into_iter().chain([res]).collect())
             },
             Err(err) => {
                 Err(Error::new(ParseError{
                     chapter: self.chapter_name.clone(),
+                    file: self.source_file.clone(),
+                    location: err.downcast_ref::<Diagnostic>().map(|diagnostic| self.locate(diagnostic.line, diagnostic.column)),
                     source: err,
                 }))
             },
         }
     }
+
+    /// Runs the validator of the config over the parsed messages, fails at the first error and logs the warnings
+    fn validate(&self, components: Components) -> Result<Components> {
+        let problems = self.config.validator.validate(&components, &self.config);
+        if let Some(problem) = problems.iter().find(|problem| problem.severity == Severity::Error) {
+            return Err(match self.parser.locate(self, problem) {
+                Some((field, line, column)) => Diagnostic::new(format!("invalid message: {}", problem.message), field, line, column).anyhow(),
+                None => anyhow!("Invalid message #{}: {}", problem.index + 1, problem),
+            });
+        }
+        for problem in problems {
+            match self.parser.locate(self, &problem) {
+                Some((field, line, column)) => self.warn(Diagnostic::new(problem.message, field, line, column), Some((line, column))),
+                None => self.warn(format!("message #{}: {}", problem.index + 1, problem), None),
+            }
+        }
+        Ok(components)
+    }
+
+    /// Logs a problem which doesn't stop the block from being built, at a one based line and column of the code when known
+    fn warn(&self, message: impl Display, position: Option<(usize, usize)>) {
+        let location = position.map(|(line, column)| self.locate(line, column).to_string()).unwrap_or_default();
+        eprintln!("Warning: A discord code block in chapter '{}': {}{}", self.chapter_name, message, location);
+    }
+
+    /// Where a one based line and column of the code is in the source file, with the line of code it points at
+    fn locate(&self, line: usize, column: usize) -> Location {
+        let lines = self.code.lines().collect::<Vec<_>>();
+        // Errors at the end of the code, like an unclosed bracket, point after its last line
+        let (code_line, code_column) = match lines.last() {
+            Some(last) if line > lines.len() => (lines.len() - 1, last.chars().count() + 1),
+            _ => (line.saturating_sub(1), column),
+        };
+        let (line, column) = match self.positions.iter().rev().find(|position| position.code_line <= code_line) {
+            Some(position) => (position.line + code_line - position.code_line, position.column + code_column - 1),
+            None => (code_line + 1, code_column),
+        };
+        Location{
+            file: self.source_file.clone(),
+            line,
+            column,
+            snippet: lines.get(code_line).copied().unwrap_or_default().to_owned(),
+            snippet_column: code_column,
+        }
+    }
+}
+
+/// Line of the code which starts at `line` and `column` of the source file
+#[derive(Debug, Clone, Copy)]
+struct CodePosition {
+    code_line: usize,
+    line: usize,
+    column: usize,
+}
+
+/// Error at a one based line and column of the code, which [`ParseError`] shows in the source file
+#[derive(Debug)]
+struct Diagnostic {
+    message: String,
+    /// Path of the offending field, like `[2].embeds[0].fields[1].name`
+    field: Option<String>,
+    line: usize,
+    column: usize,
+}
+
+impl Diagnostic {
+    fn new<S: Into<String>>(message: S, field: Option<String>, line: usize, column: usize) -> Self {
+        Self { message: message.into(), field, line, column }
+    }
+
+    fn anyhow(self) -> anyhow::Error {
+        anyhow::Error::new(self)
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.field.as_ref() {
+            Some(field) => write!(f, "{}: {}", field, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Position in the source file, shown on the lines below a message like rustc's diagnostics
+#[derive(Debug)]
+struct Location {
+    file: Option<PathBuf>,
+    line: usize,
+    column: usize,
+    snippet: String,
+    snippet_column: usize,
 }
 
 #[derive(Debug)]
 struct ParseError {
     chapter: String,
+    file: Option<PathBuf>,
+    location: Option<Location>,
     source: Error,
 }
 
 impl Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Failed to parse a discord code block in chapter '{}'! Error: {}", self.chapter, self.source)
+        write!(f, "Failed to parse a discord code block in chapter '{}'! Error: {}", self.chapter, self.source)?;
+        match (self.location.as_ref(), self.file.as_ref()) {
+            (Some(location), _) => write!(f, "{location}"),
+            (None, Some(file)) => write!(f, "\n --> {}", file.display()),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        let caret = self.snippet.chars()
+            .take(self.snippet_column.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        write!(f, "\n{gutter}--> ")?;
+        if let Some(file) = self.file.as_ref() {
+            write!(f, "{}:", file.display())?;
+        }
+        write!(f, "{}:{}", self.line, self.column)?;
+        write!(f, "\n{gutter} |\n{} | {}\n{gutter} | {caret}^", self.line, self.snippet)
     }
 }
 
 impl std::error::Error for ParseError {}
+
+impl<P: Parser, G: Generator> CodeBlock<G> for DiscordCodeBlock<P> {
+    fn block_name(&self) -> &str {
+        &self.block_name
+    }
+
+    fn code(&self) -> &str {
+        &self.code
+    }
+
+    fn push_code(&mut self, code: &str) {
+        DiscordCodeBlock::push_code(self, code)
+    }
+
+    fn set_arguments(&mut self, arguments: &str) {
+        DiscordCodeBlock::set_arguments(self, arguments)
+    }
+
+    fn set_source_file(&mut self, path: PathBuf) {
+        DiscordCodeBlock::set_source_file(self, path)
+    }
+
+    fn set_position(&mut self, line: usize, column: usize) {
+        DiscordCodeBlock::set_position(self, line, column)
+    }
+
+    fn set_path_to_root(&mut self, path: &str) {
+        DiscordCodeBlock::set_path_to_root(self, path)
+    }
+
+    fn skip_head(&mut self) {
+        DiscordCodeBlock::skip_head(self)
+    }
+
+    fn build<'a>(&self) -> Result<Vec<Event<'a>>> {
+        DiscordCodeBlock::build::<G>(self)
+    }
+}
+
+/// Resolves the names of users mentioned by ID, from the personas first and the Discord API second
+fn resolve_user_mentions(components: &mut Components, config: &Config) {
+    let mut ids = HashSet::new();
+    collect_user_mentions(&components.tree, &mut ids);
+    for id in ids {
+        let name = config.personas.values()
+            .find(|persona| persona.user_id == Some(id))
+            .and_then(|persona| persona.username.clone())
+            .or_else(|| fetch_user_name(id));
+        if let Some(name) = name {
+            components.users.insert(id, name);
+        }
+    }
+}
+
+fn collect_user_mentions(tree: &[ComponentTree], ids: &mut HashSet<u64>) {
+    for node in tree {
+        match node {
+            ComponentTree::Text(text) => ids.extend(USER_ID_MENTION_REGEX.captures_iter(text).filter_map(|c| c[1].parse::<u64>().ok())),
+            ComponentTree::Node { nodes, .. } => collect_user_mentions(nodes, ids),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+fn fetch_user_name(user_id: u64) -> Option<String> {
+    DISCORD_CLIENT.user(user_id).map(|user| user.display_name())
+}
+
+#[cfg(not(feature = "http"))]
+fn fetch_user_name(_user_id: u64) -> Option<String> {
+    None
+}
+
+/// Formats an ISO 8601 timestamp as `MM/DD/YYYY`, leaving other values untouched
+fn format_date(timestamp: &str) -> String {
+    let date = timestamp.get(..10).unwrap_or_default();
+    let mut parts = date.split('-');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(year), Some(month), Some(day)) if year.len() == 4 && month.len() == 2 && day.len() == 2 => format!("{month}/{day}/{year}"),
+        _ => timestamp.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Persona;
+    use super::*;
+
+    #[test]
+    fn mentioned_ids_are_named_after_personas() {
+        let spen = Persona { user_id: Some(30), username: Some("Spen".to_owned()), ..Default::default() };
+        let config = Config { personas: [("spen".to_owned(), spen)].into(), ..Default::default() };
+        let mut components = Components { tree: vec![ComponentTree::Text("Hi <@30> and <@!30>".to_owned())], ..Default::default() };
+        resolve_user_mentions(&mut components, &config);
+        assert_eq!(components.users.get(&30).map(String::as_str), Some("Spen"));
+        assert_eq!(components.users.len(), 1);
+    }
+}
@@ -4,12 +4,19 @@ use std::{
     collections::HashMap
 };
 use convert_case::{Casing, Case};
-use serde::Deserialize;
+use serde::{de::{DeserializeSeed, SeqAccess, Visitor}, Deserialize, Deserializer};
+use serde_aux::serde_introspection::serde_introspect;
+use serde_yaml::Value;
+use regex::Regex;
 use anyhow::Result;
 
 #[cfg(feature = "http")]
 use super::DISCORD_CLIENT;
-use super::{DiscordCodeBlock, Parser};
+use super::{Diagnostic, DiscordCodeBlock, Parser};
+use crate::validation::{Problem, Severity};
+use crate::schema::{self, JsonSchema};
+use crate::config::{Config, Persona};
+use crate::emoji;
 use crate::components::{
     components::*,
     message::*,
@@ -18,83 +25,471 @@ use crate::components::{
     *,
 };
 
+lazy_static::lazy_static! {
+    static ref FIELD_PATH_REGEX: Regex = Regex::new(r"^([\w\[\].?-]+): ").unwrap();
+    static ref LINE_COLUMN_REGEX: Regex = Regex::new(r" at line \d+ column \d+").unwrap();
+    pub(super) static ref FIELD_SEGMENT_REGEX: Regex = Regex::new(r"\[(\d+)\]|([^.\[\]]+)").unwrap();
+}
+
 pub struct YamlParser;
 
 impl Parser for YamlParser {
     fn new() -> Self { Self{} }
 
     fn parse<P: Parser>(&self, code_block: &DiscordCodeBlock<P>) -> Result<Components> {
+        let code = &code_block.code;
+        let value = serde_yaml::from_str::<Value>(code).map_err(|err| diagnostic(code, err))?;
+        let single = !value.is_sequence();
+        // Fields are located in the code, the paths of a single message don't start with its index
+        let field_path = |i: usize, invalid: Invalid| {
+            let field = [(!single).then(|| format!("[{i}]")), invalid.field].into_iter().flatten().collect::<Vec<_>>().join(".");
+            (locate(code, &field), Some(field).filter(|f| !f.is_empty()), invalid.message)
+        };
+        // Checked first, as a misspelled field otherwise shows up as a missing one
+        for (i, invalid) in unknown_fields(&value) {
+            let (position, field, message) = field_path(i, invalid);
+            if code_block.config.deny_unknown_fields {
+                return Err(Diagnostic::new(message, field, position.0, position.1).anyhow());
+            }
+            code_block.warn(Diagnostic::new(message, field, position.0, position.1), Some(position));
+        }
+        // A single message may be written without the surrounding list
+        let messages = match value {
+            Value::Sequence(ref items) => MessagesSeed(items.iter().map(MessageKind::of).collect()).deserialize(serde_yaml::Deserializer::from_str(code)),
+            ref value => MessageSeed(MessageKind::of(value)).deserialize(serde_yaml::Deserializer::from_str(code)).map(|message| vec![message]),
+        };
         let mut components = Components::default();
-        match serde_yaml::from_str::<Vec<YamlMessage>>(&code_block.code) {
-            Ok(mut m) => {
-                for (i, mut mess) in m.drain(..).enumerate() {
-                    mess.prepare();
-                    if let Some(err) = mess.is_valid() {
-                        return Err(YamlParserError::new(format!("Invalid message #{}: {}", i+1, err)).anyhow());
-                    }
-                    mess.push_to_tree(&mut components);
-                }
+        push_messages(messages.map_err(|err| diagnostic(code, err))?, &mut components, &code_block.config).map_err(|(i, invalid)| {
+            let ((line, column), field, message) = field_path(i, invalid);
+            Diagnostic::new(format!("invalid message: {message}"), field, line, column).anyhow()
+        })?;
+        Ok(components)
+    }
+
+    fn locate<P: Parser>(&self, code_block: &DiscordCodeBlock<P>, problem: &Problem) -> Option<(Option<String>, usize, usize)> {
+        let code = &code_block.code;
+        let (message, index) = match serde_yaml::from_str::<Value>(code).ok()? {
+            Value::Sequence(mut items) if problem.index < items.len() => (items.swap_remove(problem.index), Some(format!("[{}]", problem.index))),
+            Value::Sequence(_) => return None,
+            value => (value, None),
+        };
+        let field = problem.field.as_deref().map(|field| yaml_field(field, &message));
+        let path = [index, field].into_iter().flatten().collect::<Vec<_>>().join(".");
+        let (line, column) = locate(code, &path);
+        Some((Some(path).filter(|path| !path.is_empty()), line, column))
+    }
+}
+
+impl YamlParser {
+    /// JSON Schema of the YAML model, a message or a list of messages, for editors to complete and check blocks
+    pub fn schema() -> serde_json::Value {
+        let mut definitions = serde_json::Map::new();
+        // Like `MessageKind::of`, mappings with a `type` are system messages
+        let message = serde_json::json!({
+            "description": "A message, or a system message when it has a `type`",
+            "if": { "required": ["type"] },
+            "then": YamlSystemMessage::schema(&mut definitions),
+            "else": YamlBasicMessage::schema(&mut definitions),
+        });
+        definitions.insert("Message".to_owned(), message);
+        // A single message may be written without the surrounding list
+        let root = serde_json::json!({
+            "type": ["array", "object"],
+            "items": schema::reference("Message"),
+            "if": { "type": "object" },
+            "then": schema::reference("Message"),
+        });
+        schema::document("discord-components YAML messages", root, definitions)
+    }
+
+    /// Builds components from a YAML sequence of messages, used by parsers which desugar into the YAML model
+    pub(super) fn parse_value<P: Parser>(value: Value, code_block: &DiscordCodeBlock<P>) -> Result<Components> {
+        let kinds = value.as_sequence().map(|items| items.iter().map(MessageKind::of).collect()).unwrap_or_default();
+        let mut components = Components::default();
+        push_messages(MessagesSeed(kinds).deserialize(value)?, &mut components, &code_block.config).map_err(|(i, invalid)| {
+            // Located in the code by the parser which desugared it
+            let problem = Problem { severity: Severity::Error, index: i, field: invalid.field, message: invalid.message };
+            match code_block.parser.locate(code_block, &problem) {
+                Some((field, line, column)) => Diagnostic::new(format!("invalid message: {}", problem.message), field, line, column).anyhow(),
+                None => YamlParserError::new(format!("Invalid message #{}: {}", i+1, problem.message)).anyhow(),
+            }
+        })?;
+        Ok(components)
+    }
+}
+
+/// Adds the messages to the components, or returns the index of the first message with an unknown persona.
+/// The messages themselves are checked by the validator, once the components are built
+fn push_messages(mut messages: Vec<YamlMessage>, components: &mut Components, config: &Config) -> std::result::Result<(), (usize, Invalid)> {
+    for (i, mut mess) in messages.drain(..).enumerate() {
+        mess.prepare(config).map_err(|invalid| (i, invalid))?;
+        mess.push_to_tree(components);
+    }
+    Ok(())
+}
+
+/// Path of a field of the validated components in a YAML message, where embed authors have a `text`
+/// and the first embed may be written as `embed`
+fn yaml_field(field: &str, message: &Value) -> String {
+    let field = field.replace(".author.name", ".author.text");
+    if message.get("embed").is_none() {
+        return field;
+    }
+    if field == "embeds" && message.get("embeds").is_none() {
+        return "embed".to_owned();
+    }
+    let Some((index, rest)) = field.strip_prefix("embeds[").and_then(|rest| rest.split_once(']')) else {
+        return field;
+    };
+    match index.parse::<usize>() {
+        Ok(0) => format!("embed{rest}"),
+        Ok(index) => format!("embeds[{}]{rest}", index - 1),
+        Err(_) => field,
+    }
+}
+
+/// Why a message is invalid, and the field of the message at fault
+struct Invalid {
+    field: Option<String>,
+    message: String,
+}
+
+impl Invalid {
+    fn new<S: Into<String>>(field: Option<&str>, message: S) -> Self {
+        Self { field: field.map(str::to_owned), message: message.into() }
+    }
+}
+
+/// Keys of the messages which aren't fields of the YAML model, by index of their message
+fn unknown_fields(value: &Value) -> Vec<(usize, Invalid)> {
+    let messages = match value {
+        Value::Sequence(items) => items.iter().collect(),
+        value => vec![value],
+    };
+    let mut unknown = Vec::new();
+    for (i, message) in messages.into_iter().enumerate() {
+        let mut fields = Vec::new();
+        Schema::of(MessageKind::of(message)).unknown_fields(message, None, &mut fields);
+        unknown.extend(fields.into_iter().map(|invalid| (i, invalid)));
+    }
+    unknown
+}
+
+/// The structs of the YAML model, with the fields they accept
+#[derive(Debug, Clone, Copy)]
+enum Schema {
+    System,
+    Message,
+    Reply,
+    Command,
+    Embed,
+    Author,
+    Field,
+    Footer,
+    Reaction,
+    Attachment,
+    Button,
+    Invite,
+}
+
+impl Schema {
+    fn of(kind: MessageKind) -> Self {
+        match kind {
+            MessageKind::System => Schema::System,
+            MessageKind::Basic => Schema::Message,
+        }
+    }
+
+    fn fields(self) -> &'static [&'static str] {
+        match self {
+            Schema::System => serde_introspect::<YamlSystemMessage>(),
+            Schema::Message => serde_introspect::<YamlBasicMessage>(),
+            Schema::Reply => serde_introspect::<YamlReply>(),
+            Schema::Command => serde_introspect::<YamlCommand>(),
+            Schema::Embed => serde_introspect::<YamlEmbed>(),
+            Schema::Author => serde_introspect::<Author>(),
+            Schema::Field => serde_introspect::<Field>(),
+            Schema::Footer => serde_introspect::<Footer>(),
+            Schema::Reaction => serde_introspect::<YamlReaction>(),
+            Schema::Attachment => serde_introspect::<YamlAttachment>(),
+            Schema::Button => serde_introspect::<YamlButton>(),
+            Schema::Invite => serde_introspect::<YamlInvite>(),
+        }
+    }
+
+    /// The struct of a field's value, or of the items of its list
+    fn child(self, field: &str) -> Option<Schema> {
+        Some(match (self, field) {
+            (Schema::Message, "reply") => Schema::Reply,
+            (Schema::Message, "command") => Schema::Command,
+            (Schema::Message, "embed" | "embeds") => Schema::Embed,
+            (Schema::Message, "reactions") => Schema::Reaction,
+            (Schema::Message, "attachments") => Schema::Attachment,
+            (Schema::Message, "components") => Schema::Button,
+            (Schema::Message, "invites") => Schema::Invite,
+            (Schema::Embed, "author") => Schema::Author,
+            (Schema::Embed, "fields") => Schema::Field,
+            (Schema::Embed, "footer") => Schema::Footer,
+            _ => return None,
+        })
+    }
+
+    fn unknown_fields(self, value: &Value, path: Option<String>, unknown: &mut Vec<Invalid>) {
+        match value {
+            // Lists of this struct, or of lists like the action rows of buttons
+            Value::Sequence(items) => for (i, item) in items.iter().enumerate() {
+                self.unknown_fields(item, Some(format!("{}[{i}]", path.as_deref().unwrap_or_default())), unknown);
             },
-            Err(_) => {
-                let mut message = serde_yaml::from_str::<YamlMessage>(&code_block.code)?;
-                message.prepare();
-                if let Some(err) = message.is_valid() {
-                    return Err(YamlParserError::new(format!("Invalid message: {}", err)).anyhow());
+            Value::Mapping(mapping) => for (key, value) in mapping {
+                let Some(key) = key.as_str() else {
+                    continue;
+                };
+                let field = match path.as_ref() {
+                    Some(path) => format!("{path}.{key}"),
+                    None => key.to_owned(),
+                };
+                if !self.fields().contains(&key) {
+                    let message = match did_you_mean(key, self.fields()) {
+                        Some(known) => format!("unknown field `{key}`, did you mean `{known}`?"),
+                        None => format!("unknown field `{key}`, expected one of {}", self.fields().iter().map(|f| format!("`{f}`")).collect::<Vec<_>>().join(", ")),
+                    };
+                    unknown.push(Invalid{ field: Some(field), message });
+                } else if let Some(child) = self.child(key) {
+                    child.unknown_fields(value, Some(field), unknown);
                 }
-                message.push_to_tree(&mut components);
+            },
+            _ => {},
+        }
+    }
+}
+
+/// The known field closest to a misspelled one, if it's close enough to be what was meant
+fn did_you_mean<'a>(field: &str, known: &[&'a str]) -> Option<&'a str> {
+    known.iter()
+        .map(|known| (edit_distance(field, known), *known))
+        .filter(|(distance, known)| *distance <= known.chars().count().div_ceil(3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, known)| known)
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a != *b);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Turns a YAML error into a diagnostic, with the field path taken out of serde_yaml's message
+fn diagnostic(code: &str, err: serde_yaml::Error) -> anyhow::Error {
+    let Some(location) = err.location() else {
+        return err.into();
+    };
+    // Positions in the message are relative to the code, the diagnostic's own position replaces them
+    let message = LINE_COLUMN_REGEX.replace_all(&err.to_string(), "").into_owned();
+    match FIELD_PATH_REGEX.captures(&message) {
+        Some(captures) => {
+            // Paths into the root list are written like `.[2].content`
+            let field = captures[1].strip_prefix('.').unwrap_or(&captures[1]).to_owned();
+            // Missing fields are reported at the start of their mapping, which may be the line of the previous key
+            let (line, column) = if message.contains("missing field") { locate(code, &field) } else { (location.line(), location.column()) };
+            Diagnostic::new(&message[captures[0].len()..], Some(field), line, column).anyhow()
+        },
+        None => Diagnostic::new(message, None, location.line(), location.column()).anyhow(),
+    }
+}
+
+/// Finds the one based line and column of a field path, like `[2].reply.as`, in block style YAML.
+/// Falls back to the closest parent found, as flow style collections aren't looked into
+fn locate(code: &str, field: &str) -> (usize, usize) {
+    let lines = code.lines().enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#') && line.trim() != "---")
+        .collect::<Vec<_>>();
+    let indent = |k: usize| lines[k].1.len() - lines[k].1.trim_start().len();
+    let Some(&(first, _)) = lines.first() else {
+        return (1, 1);
+    };
+    // Values which aren't on the line of their key or list item start on the next line
+    let next_line = |k: usize, end: usize| (k + 1, if k + 1 < end { indent(k + 1) } else { 0 });
+    let mut found = (first, indent(0));
+    // The node being looked into spans `start..end` of the lines, and starts at `column` of its first line
+    let (mut start, mut end, mut column) = (0, lines.len(), indent(0));
+    for segment in FIELD_SEGMENT_REGEX.captures_iter(field) {
+        if start >= end {
+            break;
+        }
+        let at = |k: usize| if k == start { column } else { indent(k) };
+        if let Some(index) = segment.get(1).and_then(|i| i.as_str().parse::<usize>().ok()) {
+            let Some(k) = (start..end).filter(|&k| at(k) == column && is_item(&lines[k].1[column..])).nth(index) else {
+                break;
+            };
+            found = (lines[k].0, column);
+            let rest = lines[k].1[column + 1..].trim_start();
+            end = (k + 1..end).find(|&k| indent(k) <= found.1).unwrap_or(end);
+            (start, column) = if rest.is_empty() { next_line(k, end) } else { (k, lines[k].1.len() - rest.len()) };
+        } else {
+            let key = &segment[0];
+            let Some(k) = (start..end).filter(|&k| at(k) == column).find(|&k| is_key(&lines[k].1[at(k)..], key)) else {
+                break;
+            };
+            found = (lines[k].0, column);
+            let inline = lines[k].1.split_once(':').map(|(_, value)| value.trim()).unwrap_or_default();
+            end = (k + 1..end).find(|&k| indent(k) < found.1 || (indent(k) == found.1 && !is_item(lines[k].1.trim_start()))).unwrap_or(end);
+            // Values on the key's line are scalars or flow collections
+            (start, column) = if inline.is_empty() || inline.starts_with('#') { next_line(k, end) } else { (end, 0) };
+        }
+    }
+    (found.0 + 1, found.1 + 1)
+}
+
+fn is_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+fn is_key(text: &str, key: &str) -> bool {
+    [key.to_owned(), format!("\"{key}\""), format!("'{key}'")].iter()
+        .any(|key| text.strip_prefix(key.as_str()).map(|rest| rest.trim_start().starts_with(':')).unwrap_or(false))
+}
+
+/// Messages are told apart by their `type`, which only system messages have.
+/// Deserializing into the known kind keeps serde's error and its position, which an untagged enum would lose
+#[derive(Debug, Clone, Copy)]
+enum MessageKind {
+    System,
+    Basic,
+}
+
+impl MessageKind {
+    fn of(value: &Value) -> Self {
+        if value.get("type").is_some() { MessageKind::System } else { MessageKind::Basic }
+    }
+}
+
+struct MessageSeed(MessageKind);
+
+impl<'de> DeserializeSeed<'de> for MessageSeed {
+    type Value = YamlMessage;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> std::result::Result<YamlMessage, D::Error> {
+        Ok(match self.0 {
+            MessageKind::System => YamlMessage::System(YamlSystemMessage::deserialize(deserializer)?),
+            MessageKind::Basic => YamlMessage::Basic(Box::new(YamlBasicMessage::deserialize(deserializer)?)),
+        })
+    }
+}
+
+struct MessagesSeed(Vec<MessageKind>);
+
+impl<'de> DeserializeSeed<'de> for MessagesSeed {
+    type Value = Vec<YamlMessage>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> std::result::Result<Vec<YamlMessage>, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for MessagesSeed {
+    type Value = Vec<YamlMessage>;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a list of messages")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<Vec<YamlMessage>, A::Error> {
+        let mut messages = Vec::with_capacity(self.0.len());
+        for kind in self.0 {
+            match seq.next_element_seed(MessageSeed(kind))? {
+                Some(message) => messages.push(message),
+                None => break,
             }
-        };
-        Ok(components)
+        }
+        Ok(messages)
     }
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
+#[derive(Debug)]
 enum YamlMessage {
     System(YamlSystemMessage),
-    Basic(YamlBasicMessage),
+    Basic(Box<YamlBasicMessage>),
 }
 
-#[derive(Debug, Deserialize)]
+/// A message sent by a user or a bot
+#[derive(Debug, Deserialize, JsonSchema)]
 struct YamlBasicMessage {
-    #[cfg(feature = "http")]
+    /// Name of a persona to take the author data from
+    #[serde(rename = "as")]
+    persona: Option<String>,
+    /// Author user ID, the author data is looked up with the Discord API. Needs the `http` feature, without it set `username` too
     user_id: Option<u64>,
+    /// The author username, overwrites data from `user_id` and `as`
     username: Option<String>,
+    /// The author avatar URL, overwrites data from `user_id` and `as`
     avatar: Option<String>,
+    /// CSS color of the author username, like a role color
     color: Option<String>,
+    /// Text shown where the message timestamp is
     timestamp: Option<String>,
+    /// Whether the author is a bot, overwrites data from `user_id` and `as`
     bot: Option<bool>,
+    /// Whether the message was edited
     edited: Option<bool>,
+    /// Whether the message is ephemeral, only visible to the reader
     ephemeral: Option<bool>,
+    /// Whether the message is highlighted, like messages mentioning the reader
     highlight: Option<bool>,
+    /// Whether the bot is verified
     verified: Option<bool>,
 
+    /// The message this message replies to
     reply: Option<YamlReply>,
+    /// The slash command this message responds to
     command: Option<YamlCommand>,
 
+    /// Role colors by role name, used to color role mentions
     roles: Option<HashMap<String, String>>,
 
+    /// A single embed, the first one when `embeds` is present too
     embed: Option<YamlEmbed>,
+    /// Embeds of the message
     embeds: Option<Vec<YamlEmbed>>,
 
+    /// Reactions below the message
     reactions: Option<Vec<YamlReaction>>,
+    /// Image attachments
     attachments: Option<Vec<YamlAttachment>>,
+    /// Action rows of buttons
     components: Option<Vec<YamlActionRow>>,
+    /// Server invites
     invites: Option<Vec<YamlInvite>>,
 
+    /// The message content
     #[serde(default)]
     content: String,
 }
 
-#[derive(Debug, Deserialize)]
+/// A system message, like a member joining, written with a `type`
+#[derive(Debug, Deserialize, JsonSchema)]
 struct YamlSystemMessage {
+    /// The type of the system message
     r#type: SystemMessageType,
+    /// Whether the message shows a channel name change, in Discord's style
     channel_name: Option<bool>,
+    /// Text shown where the message timestamp is
     timestamp: Option<String>,
+    /// The text of the system message
     content: String,
 }
 
-#[derive(Debug, Deserialize)]
+/// The kind of a system message, which picks its icon
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 enum SystemMessageType {
     Alert,
@@ -118,7 +513,7 @@ impl YamlMessage {
         tree.tree.push(node)
     }
 
-    fn prepare(&mut self) {
+    fn prepare(&mut self, config: &Config) -> std::result::Result<(), Invalid> {
         if let YamlMessage::Basic(ref mut basic) = self {
             if let Some(embed) = basic.embed.take() {
                 let mut single = vec![embed];
@@ -128,63 +523,35 @@ impl YamlMessage {
                     basic.embeds = Some(single);
                 }
             }
+            if let Some(key) = basic.persona.take() {
+                let author = resolve_persona(config, &key, Persona{
+                    user_id: basic.user_id.take(),
+                    username: basic.username.take(),
+                    avatar: basic.avatar.take(),
+                    color: basic.color.take(),
+                    bot: basic.bot.take(),
+                    verified: basic.verified.take(),
+                }).map_err(|err| Invalid::new(Some("as"), err))?;
+                basic.username = author.username;
+                basic.avatar = author.avatar;
+                basic.color = author.color;
+                basic.bot = author.bot;
+                basic.verified = author.verified;
+            }
+            if let Some(reply) = basic.reply.as_mut() {
+                reply.prepare(config).map_err(|err| Invalid::new(Some("reply.as"), err))?;
+            }
+            if let Some(command) = basic.command.as_mut() {
+                command.prepare(config).map_err(|err| Invalid::new(Some("command.as"), err))?;
+            }
+            for reaction in basic.reactions.iter_mut().flatten() {
+                reaction.prepare(config);
+            }
+            for button in basic.components.iter_mut().flatten().flat_map(|row| row.0.iter_mut()) {
+                button.prepare(config);
+            }
         }
-    }
-
-    #[cfg(feature = "http")]
-    fn is_valid(&self) -> Option<&'static str> {
-        match self {
-            YamlMessage::Basic(ref basic) => {
-                if basic.components.is_some() && basic.components.as_ref().unwrap().len() > 5 {
-                    return Some("too many components");
-                }
-                if basic.content.is_empty() {
-                    if basic.embeds.is_none() || basic.embeds.as_ref().unwrap().is_empty() {
-                        return Some("no message content or embeds");
-                    }
-                }
-                if basic.reply.is_some() && basic.command.is_some() {
-                    return Some("message can't be a reply and a slash command at the same time");
-                }
-                if basic.user_id.is_none() && basic.username.is_none() {
-                    return Some("no user_id or username");
-                }
-            },
-            YamlMessage::System(ref system) => {
-                if system.content.is_empty() {
-                    return Some("no system message content");
-                }
-            },
-        }
-        None
-    }
-
-    #[cfg(not(feature = "http"))]
-    fn is_valid(&self) -> Option<&'static str> {
-        match self {
-            YamlMessage::Basic(ref basic) => {
-                if basic.components.is_some() && basic.components.as_ref().unwrap().len() > 5 {
-                    return Some("too many components");
-                }
-                if basic.content.is_empty() {
-                    if basic.embeds.is_none() || basic.embeds.as_ref().unwrap().is_empty() {
-                        return Some("no message content or embeds");
-                    }
-                }
-                if basic.reply.is_some() && basic.command.is_some() {
-                    return Some("message can't be a reply and a slash command at the same time");
-                }
-                if basic.username.is_none() {
-                    return Some("no username");
-                }
-            },
-            YamlMessage::System(ref system) => {
-                if system.content.is_empty() {
-                    return Some("no system message content");
-                }
-            },
-        }
-        None
+        Ok(())
     }
 
     fn into_component(self) -> (Option<HashMap<String, String>>, ComponentTree) {
@@ -197,6 +564,9 @@ impl YamlMessage {
                         message.author = user.display_name();
                         message.avatar = Some(user.avatar_url());
                         message.bot = user.is_bot();
+                    } else {
+                        // Like mentions of users which can't be looked up
+                        message.author = "unknown-user".to_owned();
                     }
                 }
                 if let Some(username) = basic.username {
@@ -255,7 +625,7 @@ impl YamlMessage {
                 if let Some(mut invites) = basic.invites {
                     attachments.append(&mut invites.drain(..).map(|v| v.into_component()).collect())
                 }
-                if attachments.len() > 0 {
+                if !attachments.is_empty() {
                     tree.push(ComponentTree::Node {
                         data: Attachments.into(),
                         nodes: attachments,
@@ -281,20 +651,31 @@ impl YamlMessage {
     }
 }
 
-#[derive(Debug, Deserialize)]
+/// A rich embed
+#[derive(Debug, Deserialize, JsonSchema)]
 struct YamlEmbed {
+    /// Embed title text
     title: Option<String>,
+    /// URL of the title
     url: Option<String>,
+    /// CSS color of the embed
     color: Option<String>,
 
+    /// Embed description text
     description: Option<String>,
 
+    /// Image URL
     image: Option<String>,
+    /// Thumbnail URL
     thumbnail: Option<String>,
+    /// Footer timestamp, like `01/31/2000`
     timestamp: Option<String>,
 
+    /// Embed author data
     author: Option<Author>,
+    /// Embed fields
     fields: Option<Vec<Field>>,
+    /// Embed footer
     footer: Option<Footer>,
 }
 
@@ -302,20 +683,22 @@ impl YamlEmbed {
     fn prepare(&mut self) {
         if let Some(timestamp) = self.timestamp.take() {
             if let Some(footer) = self.footer.as_mut() {
-                footer.timestap = Some(timestamp);
+                footer.timestamp = Some(timestamp);
             } else {
-                self.footer = Some(Footer { text: None, image: None, timestap: Some(timestamp) })
+                self.footer = Some(Footer { text: None, image: None, timestamp: Some(timestamp) })
             }
         }
     }
 
     fn into_component(self) -> ComponentTree {
-        let mut embed = Embed::default();
-        embed.embed_title = self.title;
-        embed.url = self.url;
-        embed.color = self.color;
-        embed.image = self.image;
-        embed.thumbnail = self.thumbnail;
+        let mut embed = Embed{
+            embed_title: self.title,
+            url: self.url,
+            color: self.color,
+            image: self.image,
+            thumbnail: self.thumbnail,
+            ..Default::default()
+        };
         if let Some(author) = self.author {
             embed.author_name = Some(author.text);
             embed.author_url = author.url;
@@ -343,7 +726,7 @@ impl YamlEmbed {
             tree.push(ComponentTree::Node {
                 data: EmbedFooter{
                     footer_image: footer.image,
-                    timestamp: footer.timestap,
+                    timestamp: footer.timestamp,
                 }.into(),
                 nodes: inner,
             })
@@ -355,19 +738,28 @@ impl YamlEmbed {
     }
 }
 
-#[derive(Debug, Deserialize)]
+/// The author shown at the top of an embed
+#[derive(Debug, Deserialize, JsonSchema)]
 struct Author {
+    /// Embed author name
     text: String,
+    /// Author image URL
     image: Option<String>,
+    /// URL of the author name
     url: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+/// A field of an embed
+#[derive(Debug, Deserialize, JsonSchema)]
 struct Field {
+    /// Field name
     name: String,
+    /// Field value
     value: String,
+    /// Whether the field is inline, requires `inline_index`
     #[serde(default)]
     inline: bool,
+    /// Position of the inline field in its row
     #[serde(default)]
     inline_index: usize,
 }
@@ -389,26 +781,47 @@ impl Field {
     }
 }
 
-#[derive(Debug, Deserialize)]
+/// The footer of an embed
+#[derive(Debug, Deserialize, JsonSchema)]
 struct Footer {
+    /// Footer text
     text: Option<String>,
+    /// Footer image URL
     image: Option<String>,
-    timestap: Option<String>,
+    /// Footer timestamp, like `01/31/2000`
+    #[serde(alias = "timestap")]
+    timestamp: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+/// A reaction below a message
+#[derive(Debug, Deserialize, JsonSchema)]
 struct YamlReaction {
+    /// Emoji image URL, `:shortcode:`, unicode emoji or `<:name:id>` custom emoji
     emoji: String,
+    /// The name of the reaction, used as alternative text. Defaults to the emoji shortcode
     name: Option<String>,
+    /// Reaction count
     count: Option<usize>,
+    /// Whether the reaction is interactive
     interactive: Option<bool>,
+    /// Whether the reaction shows up as reacted
     reacted: Option<bool>,
 }
 
 impl YamlReaction {
+    /// Standard emoji are replaced with their image, and name the reaction unless it has a name
+    fn prepare(&mut self, config: &Config) {
+        if let Some((url, name)) = emoji::resolve(&self.emoji, &config.emoji_base_url) {
+            self.emoji = url;
+            self.name.get_or_insert(name);
+        }
+    }
+
     fn into_component(self) -> ComponentTree {
-        let mut data = Reaction::default();
-        data.emoji = Some(self.emoji);
+        let mut data = Reaction{
+            emoji: Some(self.emoji),
+            ..Default::default()
+        };
         if let Some(name) = self.name {
             data.name = name;
         }
@@ -428,11 +841,16 @@ impl YamlReaction {
     }
 }
 
-#[derive(Debug, Deserialize)]
+/// An image attachment
+#[derive(Debug, Deserialize, JsonSchema)]
 struct YamlAttachment {
+    /// The image URL
     url: String,
+    /// The height of the image
     height: Option<u64>,
+    /// The width of the image
     width: Option<u64>,
+    /// The alternative text of the image
     alt: Option<String>,
 }
 
@@ -451,7 +869,8 @@ impl YamlAttachment {
     }
 }
 
-#[derive(Debug, Deserialize)]
+/// A row of up to five buttons
+#[derive(Debug, Deserialize, JsonSchema)]
 struct YamlActionRow(Vec<YamlButton>);
 
 impl YamlActionRow {
@@ -463,17 +882,32 @@ impl YamlActionRow {
     }
 }
 
-#[derive(Debug, Deserialize)]
+/// A button of an action row
+#[derive(Debug, Deserialize, JsonSchema)]
 struct YamlButton {
+    /// The style of the button
     r#type: YamlButtonType,
+    /// Button text
     label: String,
+    /// Whether the button is disabled
     disabled: Option<bool>,
+    /// Emoji image URL, `:shortcode:`, unicode emoji or `<:name:id>` custom emoji
     emoji: Option<String>,
+    /// The name of the emoji
     emoji_name: Option<String>,
+    /// The URL of a link button, used with the `secondary` type
     url: Option<String>,
 }
 
 impl YamlButton {
+    fn prepare(&mut self, config: &Config) {
+        let resolved = self.emoji.as_deref().and_then(|e| emoji::resolve(e, &config.emoji_base_url));
+        if let Some((url, name)) = resolved {
+            self.emoji = Some(url);
+            self.emoji_name.get_or_insert(name);
+        }
+    }
+
     fn into_component(self) -> ComponentTree {
         let data = Button{
             r#type: format!("{:?}", self.r#type).to_case(Case::Kebab),
@@ -489,7 +923,8 @@ impl YamlButton {
     }
 }
 
-#[derive(Debug, Deserialize)]
+/// The style of a button: `primary` is blue, `secondary` grey, `success` green and `destructive` red
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 enum YamlButtonType {
     Primary,
@@ -498,13 +933,20 @@ enum YamlButtonType {
     Destructive,
 }
 
-#[derive(Debug, Deserialize)]
+/// An invite to a server
+#[derive(Debug, Deserialize, JsonSchema)]
 struct YamlInvite {
+    /// Currently online member count
     online: u64,
+    /// Server member count
     members: u64,
+    /// Invite server name
     name: String,
+    /// Server icon URL
     icon: Option<String>,
+    /// Whether the server is partnered
     partnered: Option<bool>,
+    /// Whether the server is verified
     verified: Option<bool>,
 }
 
@@ -525,37 +967,77 @@ impl YamlInvite {
     }
 }
 
-#[derive(Debug, Deserialize)]
+/// The message a message replies to
+#[derive(Debug, Deserialize, JsonSchema)]
 struct YamlReply {
+    /// Name of a persona to take the author data from
+    #[serde(rename = "as")]
+    persona: Option<String>,
+    /// Referenced message author username
     #[serde(default)]
     author: String,
+    /// Referenced message content
     content: String,
 
+    /// Referenced message author avatar URL
     avatar: Option<String>,
+    /// Referenced message author role color
     color: Option<String>,
 
+    /// Whether the referenced message contains attachments
     attachment: Option<bool>,
+    /// Whether the referenced message was edited
     edited: Option<bool>,
+    /// Whether the referenced message author is a bot
     bot: Option<bool>,
+    /// Whether the referenced message author is a verified bot
     verified: Option<bool>,
+    /// Whether the reply mentions the referenced message author
     mentions: Option<bool>,
+    /// Whether the referenced message author was the original poster in a thread
     op: Option<bool>,
+    /// Whether the referenced message was a response to a slash command
     command: Option<bool>,
 
-    #[cfg(feature = "http")]
+    /// Referenced message author ID, overrides author, avatar and bot in builds with the `http` feature
     user_id: Option<u64>,
 }
 
 impl YamlReply {
-    fn into_component(mut self) -> ComponentTree {
-        #[cfg(feature = "http")]
-        if let Some(user_id) = self.user_id {
-            if let Some(user) = DISCORD_CLIENT.user(user_id) {
-                self.author = user.display_name();
-                self.avatar = Some(user.avatar_url());
-                self.bot = Some(user.is_bot());
-            }
+    fn prepare(&mut self, config: &Config) -> std::result::Result<(), String> {
+        if let Some(key) = self.persona.take() {
+            let author = resolve_persona(config, &key, Persona{
+                user_id: self.user_id.take(),
+                username: Some(std::mem::take(&mut self.author)).filter(|a| !a.is_empty()),
+                avatar: self.avatar.take(),
+                color: self.color.take(),
+                bot: self.bot.take(),
+                verified: self.verified.take(),
+            })?;
+            self.author = author.username.unwrap_or_default();
+            self.avatar = author.avatar;
+            self.color = author.color;
+            self.bot = author.bot;
+            self.verified = author.verified;
+        }
+        Ok(())
+    }
+
+    /// Overrides the author with the user of `user_id`
+    #[cfg(feature = "http")]
+    fn fetch_user(&mut self) {
+        if let Some(user) = self.user_id.and_then(|id| DISCORD_CLIENT.user(id)) {
+            self.author = user.display_name();
+            self.avatar = Some(user.avatar_url());
+            self.bot = Some(user.is_bot());
         }
+    }
+
+    #[cfg(not(feature = "http"))]
+    fn fetch_user(&mut self) {}
+
+    fn into_component(mut self) -> ComponentTree {
+        self.fetch_user();
         let data = Reply{
             author: self.author,
             avatar: self.avatar,
@@ -575,26 +1057,57 @@ impl YamlReply {
     }
 }
 
-#[derive(Debug, Deserialize)]
+/// The slash command a message responds to
+#[derive(Debug, Deserialize, JsonSchema)]
 struct YamlCommand {
+    /// Name of a persona to take the author data from
+    #[serde(rename = "as")]
+    persona: Option<String>,
+    /// The invoked slash command name
     command: String,
+    /// The user who executed the slash command
     #[serde(default)]
     author: String,
+    /// Executing user avatar URL
     avatar: Option<String>,
+    /// Executing user role color
     color: Option<String>,
-    #[cfg(feature = "http")]
+    /// Executing user ID, overrides author and avatar in builds with the `http` feature
     user_id: Option<u64>,
 }
 
 impl YamlCommand {
-    fn into_component(mut self) -> ComponentTree {
-        #[cfg(feature = "http")]
-        if let Some(user_id) = self.user_id {
-            if let Some(user) = DISCORD_CLIENT.user(user_id) {
-                self.author = user.display_name();
-                self.avatar = Some(user.avatar_url());
-            }
+    fn prepare(&mut self, config: &Config) -> std::result::Result<(), String> {
+        if let Some(key) = self.persona.take() {
+            let author = resolve_persona(config, &key, Persona{
+                user_id: self.user_id.take(),
+                username: Some(std::mem::take(&mut self.author)).filter(|a| !a.is_empty()),
+                avatar: self.avatar.take(),
+                color: self.color.take(),
+                bot: None,
+                verified: None,
+            })?;
+            self.author = author.username.unwrap_or_default();
+            self.avatar = author.avatar;
+            self.color = author.color;
+        }
+        Ok(())
+    }
+
+    /// Overrides the executing user with the user of `user_id`
+    #[cfg(feature = "http")]
+    fn fetch_user(&mut self) {
+        if let Some(user) = self.user_id.and_then(|id| DISCORD_CLIENT.user(id)) {
+            self.author = user.display_name();
+            self.avatar = Some(user.avatar_url());
         }
+    }
+
+    #[cfg(not(feature = "http"))]
+    fn fetch_user(&mut self) {}
+
+    fn into_component(mut self) -> ComponentTree {
+        self.fetch_user();
         let data = Command{
             author: self.author,
             avatar: self.avatar,
@@ -608,6 +1121,37 @@ impl YamlCommand {
     }
 }
 
+/// Resolves author data from the persona `key`, layering the explicitly set fields over it.
+/// Like in `into_component`, user IDs are looked up before the other fields of the same layer are applied.
+fn resolve_persona(config: &Config, key: &str, explicit: Persona) -> std::result::Result<Persona, String> {
+    let Some(persona) = config.personas.get(key) else {
+        return Err(format!("unknown persona '{key}'"));
+    };
+    let mut author = Persona::default();
+    for layer in [persona.clone(), explicit] {
+        author.merge(lookup_user(layer.user_id));
+        author.merge(layer);
+    }
+    Ok(author)
+}
+
+#[cfg(feature = "http")]
+fn lookup_user(user_id: Option<u64>) -> Persona {
+    user_id.and_then(|id| DISCORD_CLIENT.user(id))
+        .map(|user| Persona{
+            username: Some(user.display_name()),
+            avatar: Some(user.avatar_url()),
+            bot: Some(user.is_bot()),
+            ..Default::default()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(not(feature = "http"))]
+fn lookup_user(_user_id: Option<u64>) -> Persona {
+    Persona::default()
+}
+
 #[derive(Debug)]
 struct YamlParserError {
     message: String,
@@ -630,3 +1174,76 @@ impl Display for YamlParserError {
 }
 
 impl Error for YamlParserError {}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use super::*;
+
+    fn parse(code: &str, config: Config) -> Result<Components> {
+        let mut block = DiscordCodeBlock::<YamlParser>::new("discord yaml".to_owned(), "Test".to_owned(), Rc::new(config));
+        block.push_code(code);
+        YamlParser.parse(&block)
+    }
+
+    fn personas() -> Config {
+        let wiki = Persona { username: Some("Wiki Bot".to_owned()), color: Some("#b9a0e0".to_owned()), bot: Some(true), ..Default::default() };
+        Config { personas: [("wiki".to_owned(), wiki)].into(), ..Default::default() }
+    }
+
+    #[test]
+    fn personas_are_layered_under_message_fields() {
+        let code = "- as: wiki\n  color: '#ffffff'\n  content: Hello\n- username: Spen\n  content: Hi\n  reply:\n    as: wiki\n    content: Hello\n";
+        let components = parse(code, personas()).unwrap();
+        let message = components.tree[0].data::<Message>().unwrap();
+        assert_eq!(message.author, "Wiki Bot");
+        assert_eq!(message.role_color.as_deref(), Some("#ffffff"));
+        assert!(message.bot);
+        let (reply, _) = components.tree[1].children::<Reply>().next().unwrap();
+        assert_eq!(reply.author, "Wiki Bot");
+        assert_eq!(reply.role_color.as_deref(), Some("#b9a0e0"));
+    }
+
+    #[test]
+    fn unknown_personas_are_errors() {
+        let err = parse("- as: spen\n  content: Hello\n", personas()).err().unwrap().to_string();
+        assert_eq!(err, "[0].as: invalid message: unknown persona 'spen'");
+    }
+
+    #[test]
+    fn unknown_fields_suggest_known_ones() {
+        let code = "- username: Spen\n  content: Hello\n  embed:\n    title: Embed\n    footer:\n      txet: Footer\n";
+        let parse = |deny_unknown_fields| parse(code, Config { deny_unknown_fields, ..Default::default() });
+        assert!(parse(false).is_ok());
+        let err = parse(true).err().unwrap();
+        assert_eq!(err.to_string(), "[0].embed.footer.txet: unknown field `txet`, did you mean `text`?");
+        let diagnostic = err.downcast_ref::<Diagnostic>().unwrap();
+        assert_eq!((diagnostic.line, diagnostic.column), (6, 7));
+    }
+
+    #[test]
+    fn schema_describes_the_yaml_model() {
+        let schema = YamlParser::schema();
+        let definitions = &schema["definitions"];
+        assert_eq!(definitions["Message"]["then"]["$ref"], "#/definitions/SystemMessage");
+        assert_eq!(definitions["BasicMessage"]["properties"]["as"]["type"], "string");
+        assert_eq!(definitions["BasicMessage"]["properties"]["components"]["items"]["$ref"], "#/definitions/ActionRow");
+        assert_eq!(definitions["BasicMessage"]["additionalProperties"], false);
+        assert_eq!(definitions["Field"]["required"], serde_json::json!(["name", "value"]));
+        assert_eq!(definitions["Footer"]["properties"]["timestamp"]["description"], "Footer timestamp, like `01/31/2000`");
+        assert_eq!(definitions["ButtonType"]["enum"], serde_json::json!(["primary", "secondary", "success", "destructive"]));
+    }
+
+    #[test]
+    fn schema_lists_every_accepted_field() {
+        let schema = YamlParser::schema();
+        let definitions = &schema["definitions"];
+        // The same in every build, Discord API lookups are left out without the `http` feature
+        for definition in ["BasicMessage", "Reply", "Command"] {
+            assert_eq!(definitions[definition]["properties"]["user_id"]["type"], "integer", "{definition}");
+        }
+        assert_eq!(definitions["Footer"]["properties"]["timestap"]["description"], "Alias of `timestamp`");
+        let code = "- username: Spen\n  content: Hello\n  embed:\n    footer:\n      timestap: 01/31/2000\n";
+        assert!(parse(code, Config { deny_unknown_fields: true, ..Default::default() }).is_ok());
+    }
+}
@@ -1,98 +1,799 @@
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+    rc::Rc,
+    fs,
+};
 use mdbook::{
     preprocess::{Preprocessor, PreprocessorContext},
     errors::Result,
-    book::Book,
+    book::{Book, Chapter},
     utils,
     BookItem,
 };
 use pulldown_cmark::{Event, Tag, CodeBlockKind};
-use crate::parsers::{DiscordCodeBlock, YamlParser};
-use crate::generators::html::HTMLGenerator;
+use regex::Regex;
+use anyhow::Context;
+use crate::assets;
+use crate::parsers::{new_code_block, CodeBlock, PARSER_NAMES};
+use crate::generators::{html::HTMLGenerator, markdown::MarkdownGenerator, static_html::StaticHTMLGenerator, Generator};
+use crate::config::{Config, OnError, Output};
+
+lazy_static::lazy_static! {
+    /// Like mdBook's, which `utils::take_anchored_lines` uses
+    static ref ANCHOR_START_REGEX: Regex = Regex::new(r"ANCHOR:\s*(?P<anchor_name>[\w_-]+)").unwrap();
+    static ref DIRECTIVE_REGEX: Regex = Regex::new(r"\\?\{\{#(discord|discord-export)\s+([^\s}]+)\s*([^}]*)\}\}").unwrap();
+}
 
 pub static PREPROCESSOR_NAME: &str = "mdbook-discord-components";
+/// Name of the `book.toml` preprocessor table, `[preprocessor.discord-components]`
+pub static CONFIG_NAME: &str = "discord-components";
 pub static BASE_CODE_BLOCK_NAME: &str = "discord";
 pub struct DiscordComponentsPreprocessor;
 
+/// What `render` generates, the output of the renderers the preprocessor runs for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// discord-components elements
+    Html,
+    /// Plain HTML with the bundled stylesheet
+    StaticHtml,
+    /// Readable markdown, like for renderers other than `html`
+    Markdown,
+}
+
 impl Preprocessor for DiscordComponentsPreprocessor {
     fn name(&self) -> &str {
         PREPROCESSOR_NAME
     }
 
     fn run(&self, ctx: &PreprocessorContext, mut book: Book) -> Result<Book> {
-        let embed_script = ctx.config
-            .get_preprocessor(self.name())
-            .and_then(|c| c.get("embed-script"))
-            .and_then(|v| v.as_bool())
-            .unwrap_or(true);
+        let mut config = load_config(&ctx.config, &ctx.root)?;
+        let src_dir = ctx.root.join(&ctx.config.book.src);
+        if config.vendor_script && ctx.renderer == "html" {
+            assets::install_script(&src_dir)?;
+            config.script_src = assets::SCRIPT_FILE_NAME.to_owned();
+        }
+        let config = Rc::new(config);
 
-        self.process_chapters(&mut book.sections, embed_script)?;
+        // Only the HTML renderer runs the discord-components script, everything else gets readable markdown
+        if ctx.renderer != "html" {
+            self.process_chapters::<MarkdownGenerator, _>(&mut book.sections, &src_dir, &config)?;
+        } else if config.output == Output::Static {
+            self.process_chapters::<StaticHTMLGenerator, _>(&mut book.sections, &src_dir, &config)?;
+        } else {
+            self.process_chapters::<HTMLGenerator, _>(&mut book.sections, &src_dir, &config)?;
+        }
 
         Ok(book)
     }
 
-    fn supports_renderer(&self, renderer: &str) -> bool {
-        renderer == "html"
+    fn supports_renderer(&self, _renderer: &str) -> bool {
+        true
     }
 }
 
 impl DiscordComponentsPreprocessor {
-    fn process_chapters<'a, I>(&self, items: I, embed_script: bool) -> Result<()> where I: IntoIterator<Item = &'a mut BookItem> + 'a {
+    fn process_chapters<'a, G, I>(&self, items: I, src_dir: &Path, config: &Rc<Config>) -> Result<()> where G: Generator + 'static, I: IntoIterator<Item = &'a mut BookItem> + 'a {
         for item in items {
             if let BookItem::Chapter(ref mut chapter) = item {
-                self.process_chapters(&mut chapter.sub_items, embed_script)?;
-
-                let mut buf = String::with_capacity(chapter.content.len());
-                let events = utils::new_cmark_parser(&chapter.content, false);
-
-                let mut block: Option<DiscordCodeBlock<_>> = None;
-                let mut block_name = BlockName::default();
-
-                let mut buffer = Vec::new();
-                for event in events {
-                    if let Some(mut builder) = block.take() {
-                        match event {
-                            Event::Text(ref text) => {
-                                builder.push_code(&**text);
-                                block = Some(builder);
-                            },
-                            Event::End(Tag::CodeBlock(CodeBlockKind::Fenced(ref info))) => {
-                                assert_eq!(Some(0), (**info).find(&builder.block_name), "We must close our code block");
-                                buffer.append(&mut builder.build::<HTMLGenerator>()?);
-                            },
-                            _ => block = Some(builder),
+                self.process_chapters::<G, _>(&mut chapter.sub_items, src_dir, config)?;
+                let mut head = ChapterHead::new(chapter.path.as_deref().map(utils::fs::path_to_root).unwrap_or_default());
+                // Code blocks go first, so errors point at their lines in the chapter's file
+                let source_file = chapter.source_path.as_ref().map(|path| src_dir.join(path));
+                let content = self.process_code_blocks::<G>(&chapter.content, &chapter.name, source_file, config, &mut head)?;
+                chapter.content = self.process_directives::<G>(&content, chapter, src_dir, config, &mut head)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces discord code blocks with the generated HTML or markdown, leaving the rest of the markdown untouched
+    fn process_code_blocks<G: Generator + 'static>(&self, content: &str, chapter_name: &str, source_file: Option<PathBuf>, config: &Rc<Config>, head: &mut ChapterHead) -> Result<String> {
+        let mut output = String::with_capacity(content.len());
+        let mut last = 0;
+        // Where the markdown is parsed from, after a block extended past nested fences the rest is parsed again
+        let mut start = 0;
+
+        let mut block: Option<(Box<dyn CodeBlock<G>>, Range<usize>)> = None;
+        let mut events = utils::new_cmark_parser(content, false).into_offset_iter();
+        while let Some((event, range)) = events.next() {
+            let range = start + range.start..start + range.end;
+            if let Some((mut builder, block_range)) = block.take() {
+                match event {
+                    Event::Text(ref text) => {
+                        builder.set_position(line_number(content, range.start), column_number(content, range.start));
+                        builder.push_code(text);
+                        block = Some((builder, block_range));
+                    },
+                    Event::End(Tag::CodeBlock(_)) => {
+                        let mut block_range = block_range.start..range.end;
+                        let nested = NestedFences::after(content, block_range.clone(), builder.code());
+                        if let Some(nested) = nested.as_ref() {
+                            for (offset, line) in nested.lines.iter() {
+                                builder.set_position(line_number(content, *offset), column_number(content, *offset));
+                                builder.push_code(line);
+                                builder.push_code("\n");
+                            }
+                            block_range.end = nested.end;
+                        }
+                        if let Some(generated) = self.build_block(builder.as_mut(), config, head)? {
+                            let (range, replacement) = splice_generated(content, block_range.clone(), &generated);
+                            output.push_str(&content[last..range.start]);
+                            output.push_str(&replacement);
+                            last = range.end;
                         }
-                    } else {
-                        match event {
-                            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info)))
-                                if (**info).find(&block_name.set("yaml")) == Some(0) => {
-                                    block = Some(DiscordCodeBlock::<YamlParser>::new(block_name.string(), chapter.name.clone(), embed_script));
-                                },
-                            _ => buffer.push(event),
+                        if nested.is_some() {
+                            start = block_range.end;
+                            events = utils::new_cmark_parser(&content[start..], false).into_offset_iter();
                         }
-                    }
+                    },
+                    _ => block = Some((builder, block_range)),
                 }
+            } else if let Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info))) = event {
+                if let Some(name) = BlockName::parse(info) {
+                    block = new_code_block(&name.parser, name.string(), chapter_name.to_owned(), config.clone())
+                        .map(|mut builder| {
+                            builder.set_arguments(&name.arguments);
+                            if let Some(path) = source_file.as_ref() {
+                                builder.set_source_file(path.clone());
+                            }
+                            (builder, range)
+                        });
+                }
+            }
+        }
+        output.push_str(&content[last..]);
+        Ok(output)
+    }
 
-                pulldown_cmark_to_cmark::cmark(buffer.iter(), &mut buf)?;
+    /// Replaces `{{#discord path}}` and `{{#discord-export path range}}` directives with the rendered messages of the referenced file
+    fn process_directives<G: Generator + 'static>(&self, content: &str, chapter: &Chapter, src_dir: &Path, config: &Rc<Config>, head: &mut ChapterHead) -> Result<String> {
+        let base_dir = chapter.source_path.as_ref()
+            .and_then(|p| p.parent())
+            .map(|p| src_dir.join(p))
+            .unwrap_or_else(|| src_dir.to_owned());
+        let mut output = String::with_capacity(content.len());
+        let mut last = 0;
+        for captures in DIRECTIVE_REGEX.captures_iter(content) {
+            let directive = captures.get(0).unwrap();
+            output.push_str(&content[last..directive.start()]);
+            last = directive.end();
+            if let Some(escaped) = directive.as_str().strip_prefix('\\') {
+                output.push_str(escaped);
+                continue;
+            }
+            let (path, selection) = match captures[2].split_once(':') {
+                Some((path, selection)) => (base_dir.join(path), Some(selection)),
+                None => (base_dir.join(&captures[2]), None),
+            };
+            let code = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read discord file '{}' in chapter '{}'", path.display(), chapter.name));
+            let code = match code {
+                Ok(code) => code,
+                Err(err) => {
+                    push_directive_output(&mut output, directive.as_str(), handle_error::<G>(err, &captures[1], directive.as_str(), config)?);
+                    continue;
+                },
+            };
+            let parser = if &captures[1] == "discord-export" { "export" } else { parser_for_path(&path) };
+            let mut block = new_code_block::<G>(parser, captures[1].to_owned(), chapter.name.clone(), config.clone())
+                .expect("Directive parsers are always available");
+            let selected = take_selected_lines(&code, selection)
+                .with_context(|| format!("Failed to include discord file '{}' in chapter '{}'", path.display(), chapter.name));
+            let (selected, first_line) = match selected {
+                Ok(selected) => selected,
+                Err(err) => {
+                    push_directive_output(&mut output, directive.as_str(), handle_error::<G>(err, &captures[1], directive.as_str(), config)?);
+                    continue;
+                },
+            };
+            block.set_position(first_line, 1);
+            block.push_code(&selected);
+            block.set_arguments(&captures[3]);
+            block.set_source_file(path);
+            let generated = self.build_block(block.as_mut(), config, head)?;
+            push_directive_output(&mut output, directive.as_str(), generated);
+        }
+        output.push_str(&content[last..]);
+        Ok(output)
+    }
+
+    /// Renders messages outside of a book, like a discord block of a chapter, as a fragment with the head
+    /// or a complete HTML page named after the source file. Blocks which fail to parse are left as written with `on-error = "warn"`
+    pub fn render(&self, code: &str, parser: &str, source_file: Option<PathBuf>, config: &Rc<Config>, format: Format, standalone: bool) -> Result<String> {
+        match format {
+            Format::Html => self.render_with::<HTMLGenerator>(code, parser, source_file, config, standalone),
+            Format::StaticHtml => self.render_with::<StaticHTMLGenerator>(code, parser, source_file, config, standalone),
+            Format::Markdown if standalone => Err(anyhow::anyhow!("A standalone page needs an HTML format")),
+            Format::Markdown => self.render_with::<MarkdownGenerator>(code, parser, source_file, config, false),
+        }
+    }
+
+    fn render_with<G: Generator + 'static>(&self, code: &str, parser: &str, source_file: Option<PathBuf>, config: &Rc<Config>, standalone: bool) -> Result<String> {
+        let name = source_file.as_deref().and_then(Path::file_name).map(|name| name.to_string_lossy().into_owned()).unwrap_or_else(|| "stdin".to_owned());
+        let mut block = new_code_block::<G>(parser, BASE_CODE_BLOCK_NAME.to_owned(), name.clone(), config.clone())
+            .with_context(|| format!("Unknown parser '{parser}', available parsers are {}", PARSER_NAMES.join(", ")))?;
+        block.push_code(code);
+        if let Some(path) = source_file {
+            block.set_source_file(path);
+        }
+        // A page has the head in its <head> instead
+        let mut head = ChapterHead{ path_to_root: String::new(), included: standalone };
+        let body = self.build_block(block.as_mut(), config, &mut head)?.unwrap_or_else(|| code.to_owned());
+        if !standalone {
+            return Ok(body);
+        }
+        let head = G::new(config.clone()).head("").map(|head| generated(vec![head])).unwrap_or_default();
+        let title = name.replace('&', "&amp;").replace('<', "&lt;");
+        Ok(format!("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n<title>{title}</title>\n{head}</head>\n<body>\n{body}</body>\n</html>\n"))
+    }
+
+    /// Builds the HTML or markdown of a block, or handles its error as set by `on-error`.
+    /// `None` leaves the block as written
+    fn build_block<G: Generator>(&self, block: &mut dyn CodeBlock<G>, config: &Rc<Config>, head: &mut ChapterHead) -> Result<Option<String>> {
+        let includes_head = head.apply(block);
+        match block.build() {
+            Ok(events) => Ok(Some(generated(events))),
+            Err(err) => {
+                // The next block includes the head instead
+                if includes_head {
+                    head.included = false;
+                }
+                handle_error::<G>(err, block.block_name(), block.code(), config)
+            },
+        }
+    }
+}
+
+/// The `[preprocessor.discord-components]` table of a book's configuration, with its personas file loaded
+pub fn load_config(book_config: &mdbook::Config, root: &Path) -> Result<Config> {
+    let mut config = book_config
+        .get_deserialized_opt::<Config, _>(format!("preprocessor.{CONFIG_NAME}"))?
+        .unwrap_or_default();
+    config.load_personas_file(root)?;
+    Ok(config)
+}
+
+/// The parser of an included file, by its extension: `.json` files are JSON, `.chat` files chat transcripts and the rest YAML
+pub fn parser_for_path(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => "json",
+        Some("chat") => "chat",
+        _ => "yaml",
+    }
+}
+
+/// What replaces a block which failed to parse, an error panel with `on-error = "render"`, or `None` to leave it as written
+fn handle_error<G: Generator>(err: anyhow::Error, block_name: &str, code: &str, config: &Rc<Config>) -> Result<Option<String>> {
+    match config.on_error {
+        OnError::Fail => Err(err),
+        OnError::Warn => {
+            eprintln!("Warning: {err:#}\nThe {block_name} block is left as written");
+            Ok(None)
+        },
+        OnError::Render => Ok(Some(generated(vec![G::new(config.clone()).error(&format!("{err:#}"), code)]))),
+    }
+}
 
-                chapter.content = buf;
+fn push_directive_output(output: &mut String, directive: &str, generated: Option<String>) {
+    match generated {
+        Some(generated) => {
+            output.push_str(&generated);
+            // Make sure the generated block ends before the following markdown
+            output.push('\n');
+        },
+        None => output.push_str(directive),
+    }
+}
+
+/// The head of the generated output, like the script tag, is only included by the first discord block of a chapter
+struct ChapterHead {
+    path_to_root: String,
+    included: bool,
+}
+
+impl ChapterHead {
+    fn new(path_to_root: String) -> Self {
+        ChapterHead{ path_to_root, included: false }
+    }
+
+    /// Returns whether the block includes the head
+    fn apply<G: Generator>(&mut self, block: &mut dyn CodeBlock<G>) -> bool {
+        if self.included {
+            block.skip_head();
+            false
+        } else {
+            block.set_path_to_root(&self.path_to_root);
+            self.included = true;
+            true
+        }
+    }
+}
+
+/// Concatenates the generated HTML or markdown
+fn generated(events: Vec<Event>) -> String {
+    let mut generated = String::new();
+    for event in events {
+        if let Event::Html(code) | Event::Text(code) = event {
+            generated.push_str(&code);
+        }
+    }
+    generated
+}
+
+fn line_number(content: &str, offset: usize) -> usize {
+    content[..offset].matches('\n').count() + 1
+}
+
+fn column_number(content: &str, offset: usize) -> usize {
+    let line_start = content[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    content[line_start..offset].chars().count() + 1
+}
+
+/// Lines of a discord block after the fence which CommonMark closes it with, when that fence closes a nested code block instead.
+///
+/// Messages showing code often contain fences of their own. A fence with an info string, like `` ```rust ``,
+/// can't close a code block, so inside a block fenced with three backticks it opens a nested block which the
+/// next plain fence closes. The discord block then goes on until the following plain fence, which isn't
+/// shorter than its opening fence. Nested blocks are indented as message content, so a fence at the block's
+/// own indentation is never taken for one. Without a closing fence before the end of its container or before
+/// another fence at the block's indentation, the block is left as CommonMark parses it, rather than taking in
+/// the rest of the chapter.
+struct NestedFences<'a> {
+    /// Lines taken into the block, without the prefix of their container, with their offset in the content
+    lines: Vec<(usize, &'a str)>,
+    /// Where the block really ends
+    end: usize,
+}
+
+impl<'a> NestedFences<'a> {
+    /// Looks for unclosed nested fences in the `code` of the block at `range`
+    fn after(content: &'a str, range: Range<usize>, code: &str) -> Option<Self> {
+        let line_start = content[..range.start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let prefix = content[line_start..range.start].chars()
+            .map(|c| if c == '>' || c == '\t' { c } else { ' ' })
+            .collect::<String>();
+        let fence = Fence::parse(content[range.start..].lines().next().unwrap_or_default())?;
+        let mut depth = 0usize;
+        for line in code.lines() {
+            depth = fence.nesting(line, depth);
+        }
+        // The block is only cut short if it ends with an indented closing fence, which closes the innermost nested block
+        let block = content[..range.end].trim_end_matches('\n');
+        let closing_start = block.rfind('\n').map(|i| i + 1).unwrap_or(0).max(line_start);
+        let closing = strip_container_prefix(&block[closing_start..], &prefix)?;
+        if depth == 0 || !fence.closes(closing) || indent(closing) <= fence.indent {
+            return None;
+        }
+        depth -= 1;
+
+        let mut lines = vec![(block.len() - closing.len(), closing)];
+        let mut offset = block.len() + 1;
+        while offset <= content.len() {
+            let line_end = content[offset..].find('\n').map(|i| offset + i).unwrap_or(content.len());
+            let line = strip_container_prefix(&content[offset..line_end], &prefix)?;
+            if depth == 0 && fence.closes(line) {
+                return Some(Self { lines, end: line_end });
+            }
+            if fence.info(line).is_some() && indent(line) <= fence.indent {
+                return None;
             }
+            depth = fence.nesting(line, depth);
+            lines.push((line_end - line.len(), line));
+            offset = line_end + 1;
         }
-        Ok(())
+        None
+    }
+}
+
+/// The opening fence of a code block
+struct Fence {
+    char: char,
+    len: usize,
+    indent: usize,
+}
+
+impl Fence {
+    fn parse(line: &str) -> Option<Self> {
+        let indent = indent(line);
+        let line = line.trim_start();
+        let char = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+        let len = line.chars().take_while(|c| *c == char).count();
+        (len >= 3).then_some(Self { char, len, indent })
+    }
+
+    /// Length and info string of a fence of the same character
+    fn info<'a>(&self, line: &'a str) -> Option<(usize, &'a str)> {
+        let line = line.trim_start();
+        let len = line.chars().take_while(|c| *c == self.char).count();
+        (len >= 3).then(|| (len, line[len..].trim()))
+    }
+
+    fn closes(&self, line: &str) -> bool {
+        matches!(self.info(line), Some((len, "")) if len >= self.len)
     }
+
+    /// Depth of nested code blocks after the line, fences with an info string open one and plain fences close one
+    fn nesting(&self, line: &str, depth: usize) -> usize {
+        match self.info(line) {
+            Some((_, "")) => depth.saturating_sub(1),
+            Some(_) => depth + 1,
+            None => depth,
+        }
+    }
+}
+
+/// Width of the line's leading whitespace
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// The line without the blockquote markers or indentation of the container, or `None` if the line isn't in the container
+fn strip_container_prefix<'a>(line: &'a str, prefix: &str) -> Option<&'a str> {
+    line.strip_prefix(prefix)
+        .or_else(|| (line.trim_end() == prefix.trim_end()).then_some(""))
+}
+
+/// The range of `content` replaced by the HTML or markdown generated for the code block at `range`, and its replacement
+///
+/// Lines after the first are prefixed like the code block, so the output stays inside blockquotes and list items.
+/// HTML blocks can't interrupt a paragraph and only end at a blank line, so blank lines are added where needed.
+fn splice_generated(content: &str, range: Range<usize>, generated: &str) -> (Range<usize>, String) {
+    let line_start = content[..range.start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line_prefix = &content[line_start..range.start];
+    let prefix = line_prefix.chars()
+        .map(|c| if c == '>' || c == '\t' { c } else { ' ' })
+        .collect::<String>();
+    let is_blank = |line: &str| line.trim_matches(|c: char| c.is_whitespace() || c == '>').is_empty();
+
+    let mut replacement = String::new();
+    let previous_line = content[..line_start].strip_suffix('\n').map(|before| before.rsplit('\n').next().unwrap_or_default());
+    if previous_line.map(|line| !is_blank(line)).unwrap_or(false) && is_blank(line_prefix) {
+        replacement.push_str(prefix.trim_end());
+        replacement.push('\n');
+    }
+    replacement.push_str(line_prefix);
+    for (i, line) in generated.trim_end_matches('\n').split('\n').enumerate() {
+        if i > 0 {
+            replacement.push('\n');
+            replacement.push_str(if line.is_empty() { prefix.trim_end() } else { &prefix });
+        }
+        replacement.push_str(line);
+    }
+
+    // The closing fence's line break belongs to the code block
+    let end = range.end + usize::from(content[range.end..].starts_with('\n'));
+    if content[..end].ends_with('\n') {
+        replacement.push('\n');
+        let next_line = content[end..].lines().next().unwrap_or_default();
+        if !is_blank(next_line) && next_line.starts_with(&prefix) {
+            replacement.push_str(prefix.trim_end());
+            replacement.push('\n');
+        }
+    }
+    (line_start..end, replacement)
+}
+
+/// Selects lines of an included file the same way as mdBook's `{{#include}}`,
+/// either a `start:end` line range (one based, both optional) or an anchor name.
+/// Returns the selected lines and the one based line they start at, or an error for an anchor which doesn't exist
+fn take_selected_lines(code: &str, selection: Option<&str>) -> Result<(String, usize)> {
+    let Some(selection) = selection else {
+        return Ok((code.to_owned(), 1));
+    };
+    let mut parts = selection.splitn(2, ':');
+    let start = match parts.next() {
+        Some("") | None => None,
+        Some(start) => match start.parse::<usize>() {
+            Ok(start) => Some(start.saturating_sub(1)),
+            Err(_) => {
+                let anchor = code.lines()
+                    .position(|line| ANCHOR_START_REGEX.captures(line).map(|captures| &captures["anchor_name"] == start).unwrap_or(false))
+                    .ok_or_else(|| anyhow::anyhow!("No anchor '{start}' in the file"))?;
+                return Ok((utils::take_anchored_lines(code, start), anchor + 2));
+            },
+        },
+    };
+    let selected = match (start, parts.next().map(str::parse::<usize>)) {
+        (Some(start), Some(Ok(end))) => utils::take_lines(code, start..end),
+        (Some(start), Some(Err(_))) => utils::take_lines(code, start..),
+        (Some(start), None) => utils::take_lines(code, start..start + 1),
+        (None, Some(Ok(end))) => utils::take_lines(code, ..end),
+        (None, _) => code.to_owned(),
+    };
+    Ok((selected, start.unwrap_or(0) + 1))
 }
 
-#[derive(Default)]
 struct BlockName {
-    current: String,
+    parser: String,
+    arguments: String,
 }
 
 impl BlockName {
-    fn set(&mut self, new: &str) -> String {
-        self.current = new.to_owned();
-        self.string()
+    fn parse(info: &str) -> Option<Self> {
+        let mut words = info.split_whitespace();
+        if words.next() != Some(BASE_CODE_BLOCK_NAME) {
+            return None;
+        }
+        words.next()
+            .filter(|parser| PARSER_NAMES.contains(parser))
+            .map(|parser| Self { parser: parser.to_owned(), arguments: words.collect::<Vec<_>>().join(" ") })
     }
 
     fn string(&self) -> String {
-        format!("{} {}", BASE_CODE_BLOCK_NAME, self.current)
+        format!("{} {}", BASE_CODE_BLOCK_NAME, self.parser)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pulldown_cmark::Tag;
+    use crate::config::Theme;
+    use crate::components::ComponentTree;
+    use crate::validation::{Problems, Validator};
+    use super::*;
+
+    static COMPLEX_MARKDOWN: &str = r#"# Chapter {#custom-id .class}
+
+Some *emphasis*, __strong__ text, ~~strike~~ and `code`, with a hard break  
+and an escaped \*star\* plus a backslash break\
+right here.
+
+| Left | Center | Right |
+|:-----|:------:|------:|
+| `a`  | **b**  | [c][ref] |
+
+A [reference link][ref], a [collapsed one][] and a footnote[^note].
+
+[ref]: https://example.com "Example"
+[collapsed one]: <https://example.com/with space>
+[^note]: The footnote text.
+
+<div class="warning">
+
+Markdown inside an *HTML block*.
+
+</div>
+
+<!-- A comment that has to stay -->
+
+{{#include ../listings/main.rs:2:5}}
+{{#playground example.rs editable}}
+
+1. First
+   - nested [ ] item
+   - [x] task
+2. Second
+
+       indented code
+
+```rust,ignore
+fn main() {
+    println!("Hello {{name}}");
+}
+```
+
+~~~discord yaml
+- username: Not a fence we close with backticks
+  content: Hello
+~~~
+
+```discord unknown
+Left alone, there is no such parser.
+```
+
+```discordant
+Also left alone.
+```
+
+Term with trailing spaces   
+\(math\) and <kbd>Ctrl</kbd>+<kbd>C</kbd>
+"#;
+
+    /// Generates dark themed HTML without the page head, to compare the surrounding markdown
+    fn process(content: &str) -> String {
+        let config = Rc::new(Config { embed_script: false, theme: Theme::Dark, ..Default::default() });
+        let mut head = ChapterHead{ path_to_root: String::new(), included: true };
+        DiscordComponentsPreprocessor.process_code_blocks::<HTMLGenerator>(content, "Test", None, &config, &mut head).unwrap()
+    }
+
+    /// Generates the chapter with `config`, including the page head like the first discord block of a chapter
+    fn process_with<G: Generator + 'static>(content: &str, config: Config) -> Result<String> {
+        DiscordComponentsPreprocessor.process_code_blocks::<G>(content, "Test", None, &Rc::new(config), &mut ChapterHead::new(String::new()))
+    }
+
+    #[test]
+    fn chapters_without_discord_blocks_are_unchanged() {
+        let markdown = COMPLEX_MARKDOWN.replace("~~~discord yaml", "~~~yaml");
+        assert_eq!(process(&markdown), markdown);
+    }
+
+    #[test]
+    fn markdown_around_discord_blocks_is_unchanged() {
+        let markdown = COMPLEX_MARKDOWN.replace("~~~discord yaml", "~~~yaml");
+        let content = format!("{markdown}\n```discord chat\nSpen: Hello\n```\n\n{markdown}");
+        let output = process(&content);
+        assert!(output.starts_with(&format!("{markdown}\n<discord-messages>\n")));
+        assert!(output.ends_with(&format!("</discord-messages>\n\n{markdown}")));
+        assert!(output.contains("<discord-message author=\"Spen\">"));
+    }
+
+    #[test]
+    fn tilde_fences_are_replaced() {
+        let output = process(COMPLEX_MARKDOWN);
+        assert!(!output.contains("~~~discord yaml"));
+        assert!(output.contains("<discord-message author=\"Not a fence we close with backticks\">"));
+        assert_eq!(output.replace(' ', "").matches("```discordunknown").count(), 1);
+        assert!(output.contains("```discordant\nAlso left alone.\n```"));
+    }
+
+    #[test]
+    fn html_stays_inside_containers() {
+        let output = process("> Quote\n> ```discord chat\n> Spen: Hello\n> ```\n> Still quoted\n\n- Item\n  ```discord chat\n  Spen: Hello\n  ```\n- Next item\n");
+        let mut events = utils::new_cmark_parser(&output, false);
+        let quote = events.by_ref()
+            .skip_while(|e| !matches!(e, Event::Start(Tag::BlockQuote)))
+            .take_while(|e| !matches!(e, Event::End(Tag::BlockQuote)))
+            .collect::<Vec<_>>();
+        assert!(quote.iter().any(|e| matches!(e, Event::Html(html) if html.contains("<discord-messages>"))));
+        assert!(quote.iter().any(|e| matches!(e, Event::Text(text) if text.as_ref() == "Still quoted")));
+        let item = events
+            .skip_while(|e| !matches!(e, Event::Start(Tag::Item)))
+            .take_while(|e| !matches!(e, Event::End(Tag::Item)))
+            .collect::<Vec<_>>();
+        assert!(item.iter().any(|e| matches!(e, Event::Html(html) if html.contains("</discord-messages>"))));
+        assert!(output.ends_with("  </discord-messages>\n- Next item\n"));
+    }
+
+    #[test]
+    fn other_renderers_get_markdown() {
+        let output = process_with::<MarkdownGenerator>("> Quote\n> ```discord chat\n> Spen: Hello\n> Kyle: Hi\n> ```\n", Config::default()).unwrap();
+        assert_eq!(output, "> Quote\n>\n> **Spen**\n>\n> Hello\n>\n> **Kyle**\n>\n> Hi\n");
+    }
+
+    #[test]
+    fn static_html_is_a_single_html_block() {
+        let content = "```discord yaml\n- username: Spen\n  content: |\n    ```\n    first\n\n    second\n    ```\n```\n";
+        let output = process_with::<StaticHTMLGenerator>(content, Config::default()).unwrap();
+        assert!(output.contains("<pre class=\"discord-code-block\"><code>first&#10;&#10;second</code></pre>"));
+        assert!(utils::new_cmark_parser(&output, false).all(|e| matches!(e, Event::Html(_))));
+    }
+
+    #[test]
+    fn html_blocks_are_separated_from_paragraphs() {
+        let output = process("Before\n```discord chat\nSpen: Hello\n```\nAfter");
+        let events = utils::new_cmark_parser(&output, false).collect::<Vec<_>>();
+        let paragraphs = events.iter().filter(|e| matches!(e, Event::Start(Tag::Paragraph))).count();
+        assert_eq!(paragraphs, 2);
+        assert!(events.iter().any(|e| matches!(e, Event::Html(html) if html.starts_with("<discord-messages>"))));
+        assert!(events.iter().any(|e| matches!(e, Event::Text(text) if text.as_ref() == "After")));
+    }
+
+    #[test]
+    fn display_options_come_from_the_fence() {
+        let content = "```discord chat theme=light compact=false no-background\nSpen: Hello\n```\n\n```discord chat\nSpen: Hello\n```\n";
+        let output = process_with::<HTMLGenerator>(content, Config { embed_script: false, compact: true, ..Default::default() }).unwrap();
+        assert!(output.contains("<discord-messages light-theme no-background>"));
+        assert!(output.contains("<discord-messages data-auto-theme compact-mode>"));
+        assert!(!output.contains("<script"));
+    }
+
+    #[test]
+    fn errors_point_at_the_chapter_file() {
+        let config = Rc::new(Config::default());
+        let content = "Intro\n\n> ```discord yaml\n> - username: Spen\n>   content: Hi\n> - username: Kyle\n>   bot: yes please\n>   content: Hi\n> ```\n";
+        let err = DiscordComponentsPreprocessor
+            .process_code_blocks::<HTMLGenerator>(content, "Test", Some(PathBuf::from("src/chapter.md")), &config, &mut ChapterHead::new(String::new()))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Error: [1].bot: invalid type: string \"yes please\", expected a boolean"));
+        assert!(err.contains(" --> src/chapter.md:7:10\n"));
+        assert!(err.ends_with("7 |   bot: yes please\n  |        ^"));
+    }
+
+    #[test]
+    fn broken_blocks_are_handled_as_configured() {
+        let content = "```discord yaml\n- content: No author\n```\n\n```discord chat\nSpen: Hello\n```\n";
+        let process = |on_error| process_with::<HTMLGenerator>(content, Config { on_error, ..Default::default() });
+        assert!(process(OnError::Fail).is_err());
+
+        let output = process(OnError::Warn).unwrap();
+        assert!(output.starts_with("```discord yaml\n- content: No author\n```\n"));
+        assert_eq!(output.matches("<script type=\"module\"").count(), 1);
+
+        let output = process(OnError::Render).unwrap();
+        assert!(output.starts_with("<div class=\"discord-error\""));
+        assert!(output.contains("[0]: invalid message: no "));
+        assert!(output.contains("<code>- content: No author</code>"));
+        assert!(utils::new_cmark_parser(&output, false).any(|e| matches!(e, Event::Html(html) if html.starts_with("<discord-messages"))));
+    }
+
+    #[test]
+    fn included_files_select_lines_like_mdbook() {
+        let code = "a\n// ANCHOR: part\nb\nc\n// ANCHOR_END: part\nd\n// ANCHOR: greeting\ne\n// ANCHOR_END: greeting\n// ANCHOR: greet\nf\n// ANCHOR_END: greet\n";
+        let select = |selection| take_selected_lines(code, selection).unwrap();
+        assert_eq!(select(None), (code.to_owned(), 1));
+        assert_eq!(select(Some("3:4")), ("b\nc".to_owned(), 3));
+        assert_eq!(select(Some("6")), ("d".to_owned(), 6));
+        assert_eq!(select(Some(":1")), ("a".to_owned(), 1));
+        assert_eq!(select(Some("part")), ("b\nc".to_owned(), 3));
+        assert_eq!(select(Some("greet")), ("f".to_owned(), 11));
+        assert_eq!(take_selected_lines(code, Some("greets")).unwrap_err().to_string(), "No anchor 'greets' in the file");
+    }
+
+    #[test]
+    fn info_strings_with_extra_spaces_are_replaced() {
+        let output = process("```discord   chat  compact\nSpen: Hello\n```\n");
+        assert!(output.starts_with("<discord-messages compact-mode>\n"));
+    }
+
+    #[test]
+    fn longer_and_tilde_fences_contain_code_blocks() {
+        for fence in ["````", "~~~"] {
+            let output = process(&format!("{fence}discord yaml\n- username: Spen\n  content: |\n    ```\n    first\n    ```\n{fence}\nAfter\n"));
+            assert!(output.contains("<discord-code multiline>first</discord-code>"), "{fence}");
+            assert!(output.ends_with("</discord-messages>\n\nAfter\n"), "{fence}");
+        }
+    }
+
+    #[test]
+    fn nested_fences_with_info_strings_stay_in_the_block() {
+        let content = "> ```discord chat\n> Spen: Look\n>   ```rust\n>   fn main() {}\n>   ```\n> Kyle: Nice\n> ```\n> Quoted\n\n```discord chat\nSpen: Bye\n```\n";
+        let output = process(content);
+        assert!(output.contains("<discord-code multiline>fn main() {}</discord-code>"));
+        assert!(output.contains("<discord-message author=\"Kyle\">"));
+        assert!(output.contains("> </discord-messages>\n>\n> Quoted\n\n<discord-messages>"));
+        assert!(!output.contains("```"));
+    }
+
+    #[test]
+    fn unclosed_nested_fences_dont_take_in_the_chapter() {
+        let content = "```discord yaml\n- username: Spen\n  content: |\n    ```rust\n    unclosed\n```\n\nText\n\n```rust\ncode\n```\n";
+        let output = process(content);
+        assert!(output.ends_with("</discord-messages>\n\nText\n\n```rust\ncode\n```\n"));
+
+        let content = "```discord yaml\n- username: Spen\n  content: |\n    ```rust\n    unclosed\n```\n\nText\n\n```\nplain\n```\n";
+        let output = process(content);
+        assert!(output.ends_with("</discord-messages>\n\nText\n\n```\nplain\n```\n"));
+    }
+
+    #[test]
+    fn custom_rules_check_every_parser() {
+        let content = "```discord yaml\n- username: Spen\n  content: Hello\n```\n\n```discord chat\nSpen: Hello\n```\n\n\
+            ```discord json\n{\"author\": {\"id\": \"1\", \"username\": \"spen\", \"avatar\": null}, \"content\": \"Hello\"}\n```\n";
+        let validator = Validator::default().with_rule(|message: &ComponentTree, _: &Config, problems: &mut Problems| {
+            if message.text().contains("Hello") {
+                problems.error(Some("content".to_owned()), "greetings aren't allowed");
+            }
+        });
+        let output = process_with::<HTMLGenerator>(content, Config { validator, on_error: OnError::Render, ..Default::default() }).unwrap();
+        assert!(output.contains("Error: [0].content: invalid message: greetings aren't allowed&#10; --&gt; 3:3"), "{output}");
+        assert!(output.contains("Error: content: invalid message: greetings aren't allowed&#10; --&gt; 7:7"), "{output}");
+        assert!(output.contains("Error: content: invalid message: greetings aren't allowed&#10;  --&gt; 11:61"), "{output}");
+    }
+
+    #[test]
+    fn render_writes_fragments_and_pages() {
+        let config = Rc::new(Config::default());
+        let code = "- username: Spen\n  content: Hello\n";
+        let render = |format, standalone| DiscordComponentsPreprocessor.render(code, "yaml", Some(PathBuf::from("hello.yaml")), &config, format, standalone);
+
+        let fragment = render(Format::Html, false).unwrap();
+        assert!(fragment.starts_with("<script type=\"module\""));
+        assert_eq!(fragment.matches("<script type=\"module\"").count(), 1);
+        assert!(fragment.contains("<discord-messages"));
+
+        let page = render(Format::StaticHtml, true).unwrap();
+        assert!(page.starts_with("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n"));
+        assert!(page.contains("<title>hello.yaml</title>\n<style>"));
+        let body = &page[page.find("<body>").unwrap()..];
+        assert!(body.starts_with("<body>\n<div class=\"discord-messages"));
+        assert!(!body.contains("<style>"));
+
+        assert!(render(Format::Markdown, false).unwrap().contains("Hello"));
+        assert!(render(Format::Markdown, true).is_err());
+        let err = DiscordComponentsPreprocessor.render(code, "xml", None, &config, Format::Html, false).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown parser 'xml', available parsers are yaml, json, chat, export");
     }
 }
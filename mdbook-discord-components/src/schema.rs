@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use serde_json::{json, Map, Value};
+
+pub use mdbook_discord_components_derive::JsonSchema;
+
+/// Types which can describe themselves with a JSON Schema, for editors to complete and check code blocks
+pub trait JsonSchema {
+    /// The schema of the type, or a reference to it for types added to the `definitions`
+    fn schema(definitions: &mut Map<String, Value>) -> Value;
+}
+
+/// A reference to a schema of the `definitions`
+pub fn reference(definition: &str) -> Value {
+    json!({ "$ref": format!("#/definitions/{definition}") })
+}
+
+/// Adds a description to a schema, unless it's empty
+pub fn describe(mut schema: Value, description: &str) -> Value {
+    if let (Some(object), false) = (schema.as_object_mut(), description.is_empty()) {
+        object.insert("description".to_owned(), description.into());
+    }
+    schema
+}
+
+/// A draft-07 schema document, with the definitions of the types `schema` references
+pub fn document(title: &str, schema: Value, definitions: Map<String, Value>) -> Value {
+    let mut document = json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": title,
+        "definitions": definitions,
+    });
+    if let (Some(document), Value::Object(schema)) = (document.as_object_mut(), schema) {
+        document.extend(schema);
+    }
+    document
+}
+
+impl JsonSchema for String {
+    fn schema(_definitions: &mut Map<String, Value>) -> Value {
+        json!({ "type": "string" })
+    }
+}
+
+impl JsonSchema for bool {
+    fn schema(_definitions: &mut Map<String, Value>) -> Value {
+        json!({ "type": "boolean" })
+    }
+}
+
+macro_rules! unsigned_schema {
+    ($($ty:ty),*) => {
+        $(impl JsonSchema for $ty {
+            fn schema(_definitions: &mut Map<String, Value>) -> Value {
+                json!({ "type": "integer", "minimum": 0 })
+            }
+        })*
+    };
+}
+
+unsigned_schema!(u64, usize);
+
+/// Optional fields aren't required, their values have the schema of `T`
+impl<T: JsonSchema> JsonSchema for Option<T> {
+    fn schema(definitions: &mut Map<String, Value>) -> Value {
+        T::schema(definitions)
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Vec<T> {
+    fn schema(definitions: &mut Map<String, Value>) -> Value {
+        json!({ "type": "array", "items": T::schema(definitions) })
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for HashMap<String, T> {
+    fn schema(definitions: &mut Map<String, Value>) -> Value {
+        json!({ "type": "object", "additionalProperties": T::schema(definitions) })
+    }
+}
@@ -0,0 +1,306 @@
+use std::fmt::{Debug, Display};
+
+use crate::config::Config;
+use crate::components::{
+    components::*,
+    message::*,
+    embed::*,
+    *,
+};
+
+/// Discord's limits on the size of messages, see https://discord.com/developers/docs/resources/message
+mod limits {
+    pub const CONTENT: usize = 2000;
+    pub const EMBEDS: usize = 10;
+    /// Characters of all embeds of a message together, in titles, descriptions, field names and values, footers and authors
+    pub const EMBED_TOTAL: usize = 6000;
+    pub const EMBED_TITLE: usize = 256;
+    pub const EMBED_DESCRIPTION: usize = 4096;
+    pub const EMBED_FIELDS: usize = 25;
+    pub const FIELD_NAME: usize = 256;
+    pub const FIELD_VALUE: usize = 1024;
+    pub const FOOTER_TEXT: usize = 2048;
+    pub const AUTHOR_NAME: usize = 256;
+    pub const ACTION_ROWS: usize = 5;
+    pub const BUTTONS_PER_ROW: usize = 5;
+    pub const BUTTON_LABEL: usize = 80;
+    pub const ATTACHMENTS: usize = 10;
+    pub const REACTIONS: usize = 20;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Logged, the block is still built
+    Warning,
+    /// Fails the block
+    Error,
+}
+
+/// Something wrong with a message of a code block
+#[derive(Debug)]
+pub struct Problem {
+    pub severity: Severity,
+    /// Index of the message in the block
+    pub index: usize,
+    /// Path of the offending field in the message, like `embeds[0].fields[1].name`
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.field.as_ref() {
+            Some(field) => write!(f, "{}: {}", field, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Collects the problems rules find in a message
+pub struct Problems {
+    index: usize,
+    problems: Vec<Problem>,
+}
+
+impl Problems {
+    pub fn push<S: Into<String>>(&mut self, severity: Severity, field: Option<String>, message: S) {
+        self.problems.push(Problem { severity, index: self.index, field, message: message.into() });
+    }
+
+    pub fn error<S: Into<String>>(&mut self, field: Option<String>, message: S) {
+        self.push(Severity::Error, field, message)
+    }
+
+    /// For rules added by library users, the built-in rules only warn through `push`
+    #[allow(dead_code)]
+    pub fn warning<S: Into<String>>(&mut self, field: Option<String>, message: S) {
+        self.push(Severity::Warning, field, message)
+    }
+}
+
+/// A check of the messages, the nodes of a [`Message`] or [`SystemMessage`] at the root of the component tree.
+/// Implemented for closures taking the same arguments as `check`
+pub trait Rule {
+    fn check(&self, message: &ComponentTree, config: &Config, problems: &mut Problems);
+}
+
+impl<F: Fn(&ComponentTree, &Config, &mut Problems)> Rule for F {
+    fn check(&self, message: &ComponentTree, config: &Config, problems: &mut Problems) {
+        self(message, config, problems)
+    }
+}
+
+/// Runs rules over the messages every parser builds, before they are generated.
+/// The default validator has the [`Structure`] and [`Limits`] rules, more can be added with [`Validator::with_rule`]
+pub struct Validator {
+    rules: Vec<Box<dyn Rule>>,
+}
+
+impl Validator {
+    /// A validator without any rules
+    pub fn new() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    pub fn with_rule<R: Rule + 'static>(mut self, rule: R) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// All problems of all messages, in the order of the messages
+    pub fn validate(&self, components: &Components, config: &Config) -> Vec<Problem> {
+        let mut problems = Problems { index: 0, problems: Vec::new() };
+        for (index, message) in components.tree.iter().enumerate() {
+            problems.index = index;
+            for rule in &self.rules {
+                rule.check(message, config, &mut problems);
+            }
+        }
+        problems.problems
+    }
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Self::new().with_rule(Structure).with_rule(Limits)
+    }
+}
+
+impl Debug for Validator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Validator").field("rules", &self.rules.len()).finish()
+    }
+}
+
+/// Messages Discord can't show: without an author, without anything to show, a reply and a slash command at once,
+/// more than five action rows
+pub struct Structure;
+
+impl Rule for Structure {
+    fn check(&self, message: &ComponentTree, _config: &Config, problems: &mut Problems) {
+        if message.data::<SystemMessage>().is_some() {
+            if message.text().trim().is_empty() {
+                problems.error(Some("content".to_owned()), "no system message content");
+            }
+            return;
+        }
+        let Some(data) = message.data::<Message>() else {
+            return;
+        };
+        let rows = message.children::<Attachments>().flat_map(|(_, node)| node.children::<ActionRow>()).count();
+        if rows > limits::ACTION_ROWS {
+            problems.error(Some("components".to_owned()), format!("{rows} action rows, Discord allows {}", limits::ACTION_ROWS));
+        }
+        if message.children::<Reply>().next().is_some() && message.children::<Command>().next().is_some() {
+            problems.error(Some("command".to_owned()), "message can't be a reply and a slash command at the same time");
+        }
+        if data.author.is_empty() {
+            // Authors given only by `user_id` are looked up with the Discord API
+            let message = if cfg!(feature = "http") { "no user_id or username" } else { "no username, a user_id alone needs the `http` feature" };
+            problems.error(None, message);
+        }
+        let empty = message.nodes().iter().all(|node| match node {
+            ComponentTree::Text(text) => text.trim().is_empty(),
+            node => node.data::<Reply>().is_some() || node.data::<Command>().is_some(),
+        });
+        if empty {
+            problems.error(None, "no message content, embeds, attachments or components");
+        }
+    }
+}
+
+/// Parts of messages over Discord's limits, like content longer than 2000 characters.
+/// Errors with `strict-limits`, warnings otherwise
+pub struct Limits;
+
+impl Rule for Limits {
+    fn check(&self, message: &ComponentTree, config: &Config, problems: &mut Problems) {
+        if message.data::<Message>().is_none() {
+            return;
+        }
+        let severity = if config.strict_limits { Severity::Error } else { Severity::Warning };
+        let mut limits = LimitCheck { problems, severity };
+        limits.characters("content".to_owned(), Some(&message.text()), limits::CONTENT);
+        let embeds = message.children::<Embed>().collect::<Vec<_>>();
+        limits.count("embeds".to_owned(), embeds.len(), "embeds", limits::EMBEDS);
+        let mut total = 0;
+        for (i, (embed, node)) in embeds.into_iter().enumerate() {
+            let path = format!("embeds[{i}]");
+            total += limits.characters(format!("{path}.title"), embed.embed_title.as_deref(), limits::EMBED_TITLE);
+            let description = node.children::<EmbedDescription>().next().map(|(_, node)| node.text());
+            total += limits.characters(format!("{path}.description"), description.as_deref(), limits::EMBED_DESCRIPTION);
+            total += limits.characters(format!("{path}.author.name"), embed.author_name.as_deref(), limits::AUTHOR_NAME);
+            let footer = node.children::<EmbedFooter>().next().map(|(_, node)| node.text());
+            total += limits.characters(format!("{path}.footer.text"), footer.as_deref(), limits::FOOTER_TEXT);
+            let fields = node.children::<EmbedFields>().flat_map(|(_, node)| node.children::<EmbedField>()).collect::<Vec<_>>();
+            limits.count(format!("{path}.fields"), fields.len(), "fields", limits::EMBED_FIELDS);
+            for (j, (field, node)) in fields.into_iter().enumerate() {
+                total += limits.characters(format!("{path}.fields[{j}].name"), Some(&field.field_title), limits::FIELD_NAME);
+                total += limits.characters(format!("{path}.fields[{j}].value"), Some(&node.text()), limits::FIELD_VALUE);
+            }
+        }
+        limits.count("embeds".to_owned(), total, "characters in embeds", limits::EMBED_TOTAL);
+        let rows = message.children::<Attachments>().flat_map(|(_, node)| node.children::<ActionRow>());
+        for (i, (_, row)) in rows.enumerate() {
+            let buttons = row.children::<Button>().collect::<Vec<_>>();
+            limits.count(format!("components[{i}]"), buttons.len(), "buttons in the row", limits::BUTTONS_PER_ROW);
+            for (j, (_, button)) in buttons.into_iter().enumerate() {
+                limits.characters(format!("components[{i}][{j}].label"), Some(&button.text()), limits::BUTTON_LABEL);
+            }
+        }
+        limits.count("attachments".to_owned(), message.children::<Attachment>().count(), "attachments", limits::ATTACHMENTS);
+        let reactions = message.children::<Reactions>().flat_map(|(_, node)| node.children::<Reaction>()).count();
+        limits.count("reactions".to_owned(), reactions, "reactions", limits::REACTIONS);
+    }
+}
+
+/// Reports the parts of a message over a limit
+struct LimitCheck<'a> {
+    problems: &'a mut Problems,
+    severity: Severity,
+}
+
+impl LimitCheck<'_> {
+    fn characters(&mut self, field: String, text: Option<&str>, limit: usize) -> usize {
+        let len = text.map(|text| text.chars().count()).unwrap_or_default();
+        if len > limit {
+            self.problems.push(self.severity, Some(field), format!("{len} characters, Discord allows {limit}"));
+        }
+        len
+    }
+
+    fn count(&mut self, field: String, len: usize, what: &str, limit: usize) {
+        if len > limit {
+            self.problems.push(self.severity, Some(field), format!("{len} {what}, Discord allows {limit}"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use crate::parsers::{DiscordCodeBlock, Parser, YamlParser};
+    use super::*;
+
+    fn validate(code: &str, config: Config) -> Vec<Problem> {
+        let config = Rc::new(config);
+        let mut block = DiscordCodeBlock::<YamlParser>::new("discord yaml".to_owned(), "Test".to_owned(), config.clone());
+        block.push_code(code);
+        let components = YamlParser.parse(&block).unwrap_or_else(|err| panic!("{err}"));
+        config.validator.validate(&components, &config)
+    }
+
+    #[test]
+    fn messages_need_an_author_and_something_to_show() {
+        let problems = validate("- content: Hello\n- username: Spen\n  reply:\n    author: Kyle\n    content: Hi\n", Config::default());
+        let problems = problems.iter().map(|problem| (problem.index, problem.severity, problem.to_string())).collect::<Vec<_>>();
+        let author = if cfg!(feature = "http") { "no user_id or username" } else { "no username, a user_id alone needs the `http` feature" };
+        assert_eq!(problems, [
+            (0, Severity::Error, author.to_owned()),
+            (1, Severity::Error, "no message content, embeds, attachments or components".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn limits_allow_up_to_discords_maximum() {
+        let x = |n: usize| "x".repeat(n);
+        let content = |n: usize| format!("  content: {}\n", x(n));
+        let embeds = |n: usize| format!("  content: Hello\n  embeds:\n{}", "    - title: Embed\n".repeat(n));
+        let total = |n: usize| format!("  embeds:\n    - description: {}\n    - description: {}\n", x(3000), x(n - 3000));
+        let fields = |n: usize| format!("  embed:\n    fields:\n{}", "      - name: A\n        value: B\n".repeat(n));
+        let footer = |n: usize| format!("  embed:\n    footer:\n      text: {}\n", x(n));
+        let author = |n: usize| format!("  embed:\n    author:\n      text: {}\n", x(n));
+        let attachments = |n: usize| format!("  content: Hello\n  attachments:\n{}", "    - url: cat.png\n".repeat(n));
+        let reactions = |n: usize| format!("  content: Hello\n  reactions:\n{}", "    - emoji: 👍\n".repeat(n));
+        assert_limit(&content, 2000, "content", "characters");
+        assert_limit(&embeds, 10, "embeds", "embeds");
+        assert_limit(&total, 6000, "embeds", "characters in embeds");
+        assert_limit(&fields, 25, "embeds[0].fields", "fields");
+        assert_limit(&footer, 2048, "embeds[0].footer.text", "characters");
+        assert_limit(&author, 256, "embeds[0].author.name", "characters");
+        assert_limit(&attachments, 10, "attachments", "attachments");
+        assert_limit(&reactions, 20, "reactions", "reactions");
+    }
+
+    /// Checks that the fields `message` writes for a size are fine at the limit and a problem above it
+    fn assert_limit(message: &dyn Fn(usize) -> String, limit: usize, field: &str, what: &str) {
+        let problems = |n: usize| validate(&format!("- username: Spen\n{}", message(n)), Config { strict_limits: true, ..Default::default() })
+            .iter()
+            .map(Problem::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(problems(limit), Vec::<String>::new(), "{field}");
+        assert_eq!(problems(limit + 1), [format!("{field}: {} {what}, Discord allows {limit}", limit + 1)]);
+    }
+
+    #[test]
+    fn limits_fail_blocks_only_when_strict() {
+        let buttons = "      - type: primary\n        label: Button\n".repeat(6);
+        let code = format!("- username: Spen\n  content: Hello\n  components:\n    -\n{buttons}");
+        for (strict_limits, severity) in [(false, Severity::Warning), (true, Severity::Error)] {
+            let problems = validate(&code, Config { strict_limits, ..Default::default() });
+            assert_eq!(problems.len(), 1);
+            assert_eq!(problems[0].severity, severity);
+            assert_eq!(problems[0].to_string(), "components[0]: 6 buttons in the row, Discord allows 5");
+        }
+    }
+}
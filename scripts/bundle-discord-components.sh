@@ -0,0 +1,29 @@
+#!/bin/sh
+# Maintainer tool: bundles the pinned discord-components release, the same one as the default `script-src`,
+# into mdbook-discord-components/assets/discord-components.js, which the `bundled-script` feature embeds,
+# and writes its SHA-256 hash next to it. Commit both after running it. Needs npm and a network connection.
+set -eu
+
+VERSION="3.6.1"
+ESBUILD_VERSION="0.19.12"
+ASSETS="$(cd "$(dirname "$0")/.." && pwd)/mdbook-discord-components/assets"
+
+WORK_DIR="$(mktemp -d)"
+trap 'rm -rf "$WORK_DIR"' EXIT
+cd "$WORK_DIR"
+
+npm init --yes > /dev/null
+npm install --silent --no-audit --no-fund --save-exact "@skyra/discord-components-core@$VERSION" "esbuild@$ESBUILD_VERSION"
+cat > entry.js <<'JS'
+import { defineCustomElements } from '@skyra/discord-components-core/loader';
+defineCustomElements(window);
+JS
+
+mkdir -p "$ASSETS"
+npx esbuild entry.js --bundle --minify --format=iife --legal-comments=inline \
+    --banner:js="/*! @skyra/discord-components-core@$VERSION, bundled with esbuild@$ESBUILD_VERSION */" \
+    --outfile="$ASSETS/discord-components.js"
+cd "$ASSETS"
+sha256sum discord-components.js > discord-components.js.sha256
+echo "Bundled @skyra/discord-components-core@$VERSION into $ASSETS/discord-components.js"
+cat discord-components.js.sha256